@@ -1,41 +1,208 @@
-use std::borrow::Borrow;
+use std::fmt;
+use std::str::FromStr;
 
-use crate::utils::extension::Splitable;
+use crate::tlv::parser::TLV;
+use crate::utils::extension::{Extendable, Splitable};
 
-#[derive(Debug)]
+/// Largest Le of a short APDU, encoded as 00
+pub const SHORT_LE_MAX: usize = 256;
+/// Largest Lc and Le of an extended APDU, Le being encoded as 0000
+const EXTENDED_LC_MAX: usize = 0xFFFF;
+const EXTENDED_LE_MAX: usize = 0x10000;
+/// Bit of the GENERATE AC reference control parameter requesting the CDA signature
+const CDA_SIGNATURE_REQUESTED: u8 = 0x10;
+/// VERIFY qualifiers of the offline PIN, sent in plaintext or enciphered with a public key of the card
+const PLAINTEXT_PIN: u8 = 0x80;
+const ENCIPHERED_PIN: u8 = 0x88;
+
+/// Command APDU cases from ISO 7816-4 §5.1, either short (S) or extended (E)
+#[derive(Debug, PartialEq)]
+pub enum Case {
+    One,
+    TwoShort,
+    ThreeShort,
+    FourShort,
+    TwoExtended,
+    ThreeExtended,
+    FourExtended,
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Case::One => "1",
+            Case::TwoShort => "2S",
+            Case::ThreeShort => "3S",
+            Case::FourShort => "4S",
+            Case::TwoExtended => "2E",
+            Case::ThreeExtended => "3E",
+            Case::FourExtended => "4E",
+        };
+        write!(f, "Case {}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct APDU {
     pub name: &'static str,
     cla: u8,
     ins: u8,
     p1: u8,
     p2: u8,
-    lc: Option<u8>,
-    data: Option<Vec<u8>>,
-    le: Option<u8>,
+    data: Vec<u8>,
+    le: Option<usize>,
+    /// Keeps the extended encoding of parsed APDUs whose lengths would fit a short one
+    extended: bool,
 }
 
 impl APDU {
-    pub fn new(name: &'static str, cla: u8, ins: u8, p1: u8, p2: u8, lc: Option<u8>, data: Option<Vec<u8>>, le: Option<u8>) -> APDU {
-        APDU { name, cla, ins, p1, p2, lc, data, le }
+    /// Lc is derived from the data length, up to 65535, while `le` is the number of bytes expected in the response,
+    /// from 1 to 65536
+    pub fn new(name: &'static str, cla: u8, ins: u8, p1: u8, p2: u8, data: Option<Vec<u8>>, le: Option<usize>) -> Result<APDU, &'static str> {
+        let data = data.unwrap_or_default();
+        if data.len() > EXTENDED_LC_MAX {
+            return Err("C-APDU data can't be longer than 65535 bytes!");
+        }
+        check_le(le)?;
+        Ok(APDU { name, cla, ins, p1, p2, data, le, extended: false })
+    }
+
+    /// Parses a raw C-APDU, inferring its case from the length of the body following the header
+    pub fn parse(raw: &[u8]) -> Result<APDU, &'static str> {
+        if raw.len() < 4 {
+            return Err("C-APDU header is truncated!");
+        }
+        let (header, body) = raw.split_at(4);
+        let apdu = |data: &[u8], le: Option<usize>, extended: bool| APDU {
+            name: name(header[1]),
+            cla: header[0],
+            ins: header[1],
+            p1: header[2],
+            p2: header[3],
+            data: data.to_vec(),
+            le,
+            extended,
+        };
+        // Le and Lc of zero stand for their maximum value
+        let short_le = |le: u8| if le == 0 { SHORT_LE_MAX } else { usize::from(le) };
+        let extended_le = |le: &[u8]| match u16::from_be_bytes([le[0], le[1]]) {
+            0 => EXTENDED_LE_MAX,
+            le => usize::from(le),
+        };
+
+        match body {
+            [] => Ok(apdu(&[], None, false)),
+            [le] => Ok(apdu(&[], Some(short_le(*le)), false)),
+            [0x00, le @ ..] if le.len() == 2 => Ok(apdu(&[], Some(extended_le(le)), true)),
+            [0x00, lc_msb, lc_lsb, rest @ ..] if !rest.is_empty() => {
+                let lc = usize::from(lc_msb.extend(*lc_lsb));
+                match rest.len() {
+                    _ if lc == 0 => Err("C-APDU Lc can't be zero!"),
+                    length if length == lc => Ok(apdu(rest, None, true)),
+                    length if length == lc + 2 => Ok(apdu(&rest[..lc], Some(extended_le(&rest[lc..])), true)),
+                    _ => Err("C-APDU length doesn't match its Lc!"),
+                }
+            }
+            [lc, rest @ ..] if *lc != 0 => {
+                let lc = usize::from(*lc);
+                match rest.len() {
+                    length if length == lc => Ok(apdu(rest, None, false)),
+                    length if length == lc + 1 => Ok(apdu(&rest[..lc], Some(short_le(rest[lc])), false)),
+                    _ => Err("C-APDU length doesn't match its Lc!"),
+                }
+            }
+            _ => Err("Invalid C-APDU body!"),
+        }
     }
 
+    pub fn case(&self) -> Case {
+        let extended = self.extended || self.data.len() > 0xFF || self.le.is_some_and(|le| le > SHORT_LE_MAX);
+        match (self.data.is_empty(), self.le.is_some(), extended) {
+            (true, false, _) => Case::One,
+            (true, true, false) => Case::TwoShort,
+            (false, false, false) => Case::ThreeShort,
+            (false, true, false) => Case::FourShort,
+            (true, true, true) => Case::TwoExtended,
+            (false, false, true) => Case::ThreeExtended,
+            (false, true, true) => Case::FourExtended,
+        }
+    }
+
+    /// Encodes the APDU, whose lengths were checked when it was built
     pub fn to_array(&self) -> Vec<u8> {
         let mut data = [self.cla, self.ins, self.p1, self.p2].to_vec();
-        self.lc.map(|value| { data.extend(&[value]) });
-        if self.data.is_some() {
-            let mut other = self.data.clone().unwrap();
-            data.append(&mut other)
+        let lc = self.data.len();
+        // Le values of 256 and 65536 are encoded as zeroes
+        let le = self.le.unwrap_or(0);
+        match self.case() {
+            Case::One => {}
+            Case::TwoShort => data.push(le as u8),
+            Case::ThreeShort => {
+                data.push(lc as u8);
+                data.extend(&self.data);
+            }
+            Case::FourShort => {
+                data.push(lc as u8);
+                data.extend(&self.data);
+                data.push(le as u8);
+            }
+            Case::TwoExtended => {
+                data.push(0x00);
+                data.extend(&(le as u16).to_be_bytes());
+            }
+            Case::ThreeExtended => {
+                data.push(0x00);
+                data.extend(&(lc as u16).to_be_bytes());
+                data.extend(&self.data);
+            }
+            Case::FourExtended => {
+                data.push(0x00);
+                data.extend(&(lc as u16).to_be_bytes());
+                data.extend(&self.data);
+                data.extend(&(le as u16).to_be_bytes());
+            }
         }
-        self.le.map(|value| { data.extend(&[value]) });
         data
     }
 
-    pub fn with_length(&self, length: u8) -> APDU {
-        APDU::new(self.name, self.cla, self.ins, self.p1, self.p2, Some(length), None, None)
+    /// Same command expecting exactly `le` bytes, as requested by the card through 6Cxx
+    pub fn with_le(&self, le: usize) -> Result<APDU, &'static str> {
+        check_le(Some(le))?;
+        Ok(APDU { le: Some(le), ..self.clone() })
+    }
+}
+
+fn check_le(le: Option<usize>) -> Result<(), &'static str> {
+    match le {
+        Some(le) if le == 0 || le > EXTENDED_LE_MAX => Err("C-APDU Le must be from 1 to 65536!"),
+        _ => Ok(()),
+    }
+}
+
+/// Names the commands `emv` knows by their instruction byte
+fn name(ins: u8) -> &'static str {
+    match ins {
+        0x18 => "APPLICATION UNBLOCK",
+        0x1E => "APPLICATION BLOCK",
+        0x20 => "VERIFY",
+        0x24 => "PIN CHANGE/UNBLOCK",
+        0x2A => "COMPUTE CRYPTOGRAPHIC CHECKSUM",
+        0x82 => "EXTERNAL AUTHENTICATE",
+        0x84 => "GET CHALLENGE",
+        0x88 => "INTERNAL AUTHENTICATE",
+        0xA4 => "SELECT",
+        0xA8 => "GET PROCESSING OPTIONS",
+        0xAE => "GENERATE AC",
+        0xB2 => "READ RECORD",
+        0xC0 => "GET RESPONSE",
+        0xCA => "GET DATA",
+        0xD2 => "OFFLINE CHANGE PIN",
+        0xDA => "PUT DATA",
+        _ => "APDU",
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CryptogramType {
     AAC,
     ARQC,
@@ -51,85 +218,119 @@ impl CryptogramType {
         }
     }
 
-    pub fn from_str(str: &str) -> CryptogramType {
+    /// Type of the cryptogram returned, from the Cryptogram Information Data (9F27)
+    pub fn from_cid(cid: u8) -> Option<CryptogramType> {
+        match cid & 0xC0 {
+            0x00 => Some(CryptogramType::AAC),
+            0x40 => Some(CryptogramType::TC),
+            0x80 => Some(CryptogramType::ARQC),
+            _ => None,
+        }
+    }
+
+}
+
+impl FromStr for CryptogramType {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<CryptogramType, String> {
         match str {
-            "AAC" => CryptogramType::AAC,
-            "ARQC" => CryptogramType::ARQC,
-            "TC" => CryptogramType::TC,
-            _ => panic!("Unknown cryptogram type")
+            "AAC" => Ok(CryptogramType::AAC),
+            "ARQC" => Ok(CryptogramType::ARQC),
+            "TC" => Ok(CryptogramType::TC),
+            _ => Err(format!("Unknown cryptogram type {}", str))
         }
     }
 }
 
-pub fn select(aid: Vec<u8>) -> APDU {
-    let length = aid.len() as u8;
-    APDU::new("SELECT", 0x00, 0xA4, 0x04, 0x00, Some(length), Some(aid), None)
+pub fn select(aid: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("SELECT", 0x00, 0xA4, 0x04, 0x00, Some(aid), Some(SHORT_LE_MAX))
 }
 
-pub fn get_response(length: u8) -> APDU {
-    APDU::new("GET RESPONSE", 0x00, 0xC0, 0x00, 0x00, Some(length), None, None)
+pub fn get_response(length: usize) -> Result<APDU, &'static str> {
+    APDU::new("GET RESPONSE", 0x00, 0xC0, 0x00, 0x00, None, Some(length))
 }
 
-pub fn get_data(tag: u16) -> APDU {
-    APDU::new("GET DATA", 0x80, 0xCA, tag.msb(), tag.lsb(), None, None, None)
+/// EMV defines GET DATA with the proprietary class 80, while some cards only accept the interindustry class 00
+pub fn get_data(proprietary: bool, tag: u16) -> Result<APDU, &'static str> {
+    let class = if proprietary { 0x80 } else { 0x00 };
+    APDU::new("GET DATA", class, 0xCA, tag.msb(), tag.lsb(), None, Some(SHORT_LE_MAX))
 }
 
-pub fn put_data(secure: bool, tag: u16, data: Vec<u8>) -> APDU {
+pub fn put_data(secure: bool, tag: u16, data: Vec<u8>) -> Result<APDU, &'static str> {
     let class = if secure { 0x84 } else { 0x80 };
-    let length = data.len() as u8;
-    APDU::new("PUT DATA", class, 0xDA, tag.msb(), tag.lsb(), Some(length), Some(data), None)
+    APDU::new("PUT DATA", class, 0xDA, tag.msb(), tag.lsb(), Some(data), None)
 }
 
-pub fn get_processing_options() -> APDU {
-    let data = vec!(0x83, 0x00);
-    APDU::new("GET PROCESSING OPTIONS", 0x80, 0xA8, 0x00, 0x00, Some(0x02), Some(data), None)
+/// The PDOL data goes in the Command Template (83), which is empty when the card has no PDOL
+pub fn get_processing_options(pdol_data: Vec<u8>) -> Result<APDU, &'static str> {
+    let data = TLV::encode(0x83, &pdol_data);
+    APDU::new("GET PROCESSING OPTIONS", 0x80, 0xA8, 0x00, 0x00, Some(data), Some(SHORT_LE_MAX))
 }
 
-pub fn read_record(record_id: u8, sfi: u8) -> APDU {
-    APDU::new("READ RECORD", 0x00, 0xB2, record_id, sfi, None, None, None)
+pub fn read_record(record_id: u8, sfi: u8) -> Result<APDU, &'static str> {
+    APDU::new("READ RECORD", 0x00, 0xB2, record_id, sfi, None, Some(SHORT_LE_MAX))
 }
 
-pub fn external_authenticate(issuer_authentication_data: Vec<u8>) -> APDU {
-    let length = issuer_authentication_data.len() as u8;
-    APDU::new("EXTERNAL AUTHENTICATE", 0x00, 0x82, 0x00, 0x00, Some(length), Some(issuer_authentication_data), None)
+pub fn external_authenticate(issuer_authentication_data: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("EXTERNAL AUTHENTICATE", 0x00, 0x82, 0x00, 0x00, Some(issuer_authentication_data), None)
 }
 
-pub fn generate_ac(cryptogram_type: CryptogramType, cdol_data: Vec<u8>) -> APDU {
-    let length = cdol_data.len() as u8;
-    let reference_control = cryptogram_type.to_reference_control();
-    APDU::new("GENERATE AC", 0x80, 0xAE, reference_control, 0x00, Some(length), Some(cdol_data), None)
+pub fn get_challenge() -> Result<APDU, &'static str> {
+    APDU::new("GET CHALLENGE", 0x00, 0x84, 0x00, 0x00, None, Some(SHORT_LE_MAX))
 }
 
-pub fn pin_unblock(mac: Vec<u8>) -> APDU {
-    let length = mac.len() as u8;
-    APDU::new("PIN CHANGE/UNBLOCK", 0x84, 0x24, 0x00, 0x00, Some(length), Some(mac), None)
+pub fn internal_authenticate(ddol_data: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("INTERNAL AUTHENTICATE", 0x00, 0x88, 0x00, 0x00, Some(ddol_data), Some(SHORT_LE_MAX))
 }
 
-pub fn pin_change(pin: Vec<u8>, mac: Vec<u8>) -> APDU {
+/// Mastercard's mag-stripe mode command, computing the CVC3 from the UDOL data
+pub fn compute_cryptographic_checksum(udol_data: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("COMPUTE CRYPTOGRAPHIC CHECKSUM", 0x80, 0x2A, 0x8E, 0x80, Some(udol_data), Some(SHORT_LE_MAX))
+}
+
+/// Requests the cryptogram, with the Signed Dynamic Application Data of CDA when `cda` is set
+pub fn generate_ac(cryptogram_type: CryptogramType, cda: bool, cdol_data: Vec<u8>) -> Result<APDU, &'static str> {
+    let reference_control = cryptogram_type.to_reference_control() | if cda { CDA_SIGNATURE_REQUESTED } else { 0x00 };
+    APDU::new("GENERATE AC", 0x80, 0xAE, reference_control, 0x00, Some(cdol_data), Some(SHORT_LE_MAX))
+}
+
+pub fn pin_unblock(mac: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("PIN CHANGE/UNBLOCK", 0x84, 0x24, 0x00, 0x00, Some(mac), None)
+}
+
+pub fn pin_change(pin: Vec<u8>, mac: Vec<u8>) -> Result<APDU, &'static str> {
     let mut data = pin.clone();
     data.extend(mac);
-    let length = data.len() as u8;
-    APDU::new("PIN CHANGE/UNBLOCK", 0x84, 0x24, 0x00, 0x02, Some(length), Some(data), None)
+    APDU::new("PIN CHANGE/UNBLOCK", 0x84, 0x24, 0x00, 0x02, Some(data), None)
+}
+
+pub fn offline_change_pin(new_pin: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("OFFLINE CHANGE PIN", 0x80, 0xD2, 0x00, 0x88, Some(new_pin), None)
 }
 
-pub fn offline_change_pin(new_pin: Vec<u8>) -> APDU {
-    let length = new_pin.len() as u8;
-    APDU::new("OFFLINE CHANGE PIN", 0x80, 0xD2, 0x00, 0x88, Some(length), Some(new_pin), None)
+/// Verifies the offline PIN, either the plaintext PIN block built by `pin_block` or, when `enciphered`, the PIN block
+/// enciphered with a public key of the card
+pub fn verify(enciphered: bool, pin_data: Vec<u8>) -> Result<APDU, &'static str> {
+    let qualifier = if enciphered { ENCIPHERED_PIN } else { PLAINTEXT_PIN };
+    APDU::new("VERIFY", 0x00, 0x20, 0x00, qualifier, Some(pin_data), None)
 }
 
-pub fn verify(pin: Vec<u8>) -> APDU {
-    let mut data = vec!(0x24);
-    let suffix = vec!(0xFF, 0xFF, 0xFF, 0xFF, 0xFF);
-    data.extend(pin);
-    data.extend(suffix);
-    let length = data.len() as u8;
-    APDU::new("VERIFY", 0x00, 0x20, 0x00, 0x80, Some(length), Some(data), None)
+/// Plaintext PIN block: the control field 2N, the N digits of the PIN and F padding up to 8 bytes (EMV Book 3 §6.5.12)
+pub fn pin_block(pin: &str) -> Result<Vec<u8>, &'static str> {
+    if !(4..=12).contains(&pin.len()) || !pin.bytes().all(|digit| digit.is_ascii_digit()) {
+        return Err("The PIN must have 4 to 12 digits");
+    }
+    let mut nibbles = vec![0x2, pin.len() as u8];
+    nibbles.extend(pin.bytes().map(|digit| digit - b'0'));
+    nibbles.resize(16, 0xF);
+    Ok(nibbles.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
 }
 
-pub fn application_block(mac: Vec<u8>) -> APDU {
-    APDU::new("APPLICATION BLOCK", 0x84, 0x1E, 0x00, 0x00, Some(0x08), Some(mac), None)
+pub fn application_block(mac: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("APPLICATION BLOCK", 0x84, 0x1E, 0x00, 0x00, Some(mac), None)
 }
 
-pub fn application_unblock(mac: Vec<u8>) -> APDU {
-    APDU::new("APPLICATION UNBLOCK", 0x84, 0x18, 0x00, 0x00, Some(0x08), Some(mac), None)
+pub fn application_unblock(mac: Vec<u8>) -> Result<APDU, &'static str> {
+    APDU::new("APPLICATION UNBLOCK", 0x84, 0x18, 0x00, 0x00, Some(mac), None)
 }
@@ -1,5 +1,5 @@
 use crate::utils::extension::Extendable;
-use crate::tlv::parser::TLV;
+use crate::tlv::parser::{Tag, TLV};
 use std::fmt;
 
 #[derive(Debug)]
@@ -13,6 +13,22 @@ impl RAPDU {
     pub fn new(status: Status, data: &[u8]) -> RAPDU {
         RAPDU { status, raw: Vec::from(data), data: TLV::decode(Vec::from(data)),  }
     }
+
+    /// Splits a raw response into its data and the status words that end it
+    pub fn from_response(response: &[u8]) -> Result<RAPDU, &'static str> {
+        match response.len() {
+            0 | 1 => Err("Card response is missing the status words!"),
+            // Header Status
+            2 => Ok(RAPDU::new(Status::new(response[0], response[1]), &[])),
+            // Trailing Status
+            length => Ok(RAPDU::new(Status::new(response[length - 2], response[length - 1]), &response[..length - 2])),
+        }
+    }
+
+    /// Finds a primitive data object of the response, templates being already flattened
+    pub fn find(&self, tag: &Tag) -> Option<&TLV> {
+        self.data.iter().find(|tlv| tlv.tag() == tag)
+    }
 }
 
 impl fmt::Display for RAPDU {
@@ -20,48 +36,245 @@ impl fmt::Display for RAPDU {
         let raw_str: Vec<String> = self.raw.iter().map(|a| format!("{:02X}", a)).collect();
         let data_str: Vec<String> = self.data.iter().map(|a| format!("{}", a)).collect();
 
-        write!(f, "R-APDU: {:?}\n  Raw: 0x{}\n  Data: [\n{}  ]\n", self.status, raw_str.join(""), data_str.join(""))
+        write!(f, "R-APDU: {}\n  Raw: 0x{}\n  Data: [\n{}  ]\n", self.status, raw_str.join(""), data_str.join(""))
     }
 }
 
+/// Status words ending a response, along with their meaning
+#[derive(Debug)]
+pub struct Status {
+    pub sw1: u8,
+    pub sw2: u8,
+    pub kind: StatusKind,
+}
+
+/// Classification of the status words by ISO 7816-4 §5.1.3
+#[derive(Debug, PartialEq)]
+pub enum Category {
+    Normal,
+    Warning,
+    ExecutionError,
+    CheckingError,
+    Unknown,
+}
+
+/// Meaning of the status words defined by ISO 7816-4 and EMV Book 3 §6.3.5
 #[derive(Debug)]
-pub enum Status {
+pub enum StatusKind {
     ResponseAvailable { length: u8 },
     WrongLengthLe { length: u8 },
+    /// 63Cx, the PIN try counter after a failed VERIFY
+    WrongPin { tries_remaining: u8 },
+    Ok,
+    NoInformationWarning,
+    ReturnedDataCorrupted,
+    EndOfFileReached,
+    SelectedFileInvalidated,
+    FileControlInformationNotFormatted,
+    SelectedFileTerminated,
+    NoInputDataFromSensor,
+    MemoryChangedWarning,
+    FileFilledUp,
+    ExecutionError,
+    ImmediateResponseRequired,
+    MemoryChangedError,
+    MemoryFailure,
+    SecurityError,
     WrongLength,
-    ReferencedDataNotFound,
-    ConditionsOfUseNotSatisfied,
+    ClassFunctionNotSupported,
+    LogicalChannelNotSupported,
+    SecureMessagingNotSupported,
+    LastCommandOfChainExpected,
+    CommandChainingNotSupported,
+    CommandNotAllowed,
+    IncompatibleFileStructure,
     SecurityConditionNotSatisfied,
-    InstructionCodeNotSupported,
-    SelectedFileInvalidated,
+    /// Authentication method blocked, the PIN try limit having been exceeded
+    PinBlocked,
+    ReferencedDataInvalidated,
+    ConditionsOfUseNotSatisfied,
+    NoCurrentElementaryFile,
+    SecureMessagingDataMissing,
+    SecureMessagingDataIncorrect,
+    WrongParameters,
+    IncorrectData,
+    FunctionNotSupported,
     FileNotFound,
-    Ok,
+    RecordNotFound,
+    NotEnoughMemory,
+    DataInconsistentWithTlv,
+    IncorrectParameters,
+    DataInconsistentWithParameters,
+    ReferencedDataNotFound,
+    FileAlreadyExists,
+    DedicatedFileNameAlreadyExists,
+    WrongParametersOffset,
+    InstructionCodeNotSupported,
+    ClassNotSupported,
+    NoPreciseDiagnosis,
+    NoElementaryFileSelected,
+    AddressOutOfRange,
+    FileIdentifierNotFound,
+    FileInconsistentWithCommand,
     Unknown,
 }
 
 impl Status {
     pub fn new(sw1: u8, sw2: u8) -> Status {
-        match sw1 {
-            0x61 => Status::ResponseAvailable { length: sw2 },
-            0x6C => Status::WrongLengthLe { length: sw2 },
-            _ => Status::check_sw2(sw1.extend(sw2))
-        }
+        let kind = match sw1 {
+            0x61 => StatusKind::ResponseAvailable { length: sw2 },
+            0x6C => StatusKind::WrongLengthLe { length: sw2 },
+            0x63 if sw2 & 0xF0 == 0xC0 => StatusKind::WrongPin { tries_remaining: sw2 & 0x0F },
+            _ => Status::from_sw(sw1.extend(sw2))
+        };
+        Status { sw1, sw2, kind }
     }
 
-    fn check_sw2(sw: u16) -> Status {
+    /// Kind of the status words whose meaning is given by SW1 and SW2 together
+    fn from_sw(sw: u16) -> StatusKind {
         match sw {
-            0x6283 => Status::SelectedFileInvalidated,
-            0x6700 => Status::WrongLength,
-            0x6982 => Status::SecurityConditionNotSatisfied,
-            0x6985 => Status::ConditionsOfUseNotSatisfied,
-            0x6A82 => Status::FileNotFound,
-            0x6A88 => Status::ReferencedDataNotFound,
-            0x6D00 => Status::InstructionCodeNotSupported,
-            0x9000 => Status::Ok,
-            _ => {
-                println!("Unknown Response Status: {:02X?}", sw);
-                Status::Unknown
-            }
+            0x6200 => StatusKind::NoInformationWarning,
+            0x6281 => StatusKind::ReturnedDataCorrupted,
+            0x6282 => StatusKind::EndOfFileReached,
+            0x6283 => StatusKind::SelectedFileInvalidated,
+            0x6284 => StatusKind::FileControlInformationNotFormatted,
+            0x6285 => StatusKind::SelectedFileTerminated,
+            0x6286 => StatusKind::NoInputDataFromSensor,
+            0x6300 => StatusKind::MemoryChangedWarning,
+            0x6381 => StatusKind::FileFilledUp,
+            0x6400 => StatusKind::ExecutionError,
+            0x6401 => StatusKind::ImmediateResponseRequired,
+            0x6500 => StatusKind::MemoryChangedError,
+            0x6581 => StatusKind::MemoryFailure,
+            0x6600 => StatusKind::SecurityError,
+            0x6700 => StatusKind::WrongLength,
+            0x6800 => StatusKind::ClassFunctionNotSupported,
+            0x6881 => StatusKind::LogicalChannelNotSupported,
+            0x6882 => StatusKind::SecureMessagingNotSupported,
+            0x6883 => StatusKind::LastCommandOfChainExpected,
+            0x6884 => StatusKind::CommandChainingNotSupported,
+            0x6900 => StatusKind::CommandNotAllowed,
+            0x6981 => StatusKind::IncompatibleFileStructure,
+            0x6982 => StatusKind::SecurityConditionNotSatisfied,
+            0x6983 => StatusKind::PinBlocked,
+            0x6984 => StatusKind::ReferencedDataInvalidated,
+            0x6985 => StatusKind::ConditionsOfUseNotSatisfied,
+            0x6986 => StatusKind::NoCurrentElementaryFile,
+            0x6987 => StatusKind::SecureMessagingDataMissing,
+            0x6988 => StatusKind::SecureMessagingDataIncorrect,
+            0x6A00 => StatusKind::WrongParameters,
+            0x6A80 => StatusKind::IncorrectData,
+            0x6A81 => StatusKind::FunctionNotSupported,
+            0x6A82 => StatusKind::FileNotFound,
+            0x6A83 => StatusKind::RecordNotFound,
+            0x6A84 => StatusKind::NotEnoughMemory,
+            0x6A85 => StatusKind::DataInconsistentWithTlv,
+            0x6A86 => StatusKind::IncorrectParameters,
+            0x6A87 => StatusKind::DataInconsistentWithParameters,
+            0x6A88 => StatusKind::ReferencedDataNotFound,
+            0x6A89 => StatusKind::FileAlreadyExists,
+            0x6A8A => StatusKind::DedicatedFileNameAlreadyExists,
+            0x6B00 => StatusKind::WrongParametersOffset,
+            0x6D00 => StatusKind::InstructionCodeNotSupported,
+            0x6E00 => StatusKind::ClassNotSupported,
+            0x6F00 => StatusKind::NoPreciseDiagnosis,
+            0x9000 => StatusKind::Ok,
+            0x9400 => StatusKind::NoElementaryFileSelected,
+            0x9402 => StatusKind::AddressOutOfRange,
+            0x9404 => StatusKind::FileIdentifierNotFound,
+            0x9408 => StatusKind::FileInconsistentWithCommand,
+            _ => StatusKind::Unknown,
         }
     }
+
+    pub fn category(&self) -> Category {
+        match self.sw1 {
+            0x90 | 0x61 => Category::Normal,
+            0x62 | 0x63 => Category::Warning,
+            0x64..=0x66 => Category::ExecutionError,
+            0x67..=0x6F => Category::CheckingError,
+            // Proprietary SW1 values, 94xx being used by cards for referencing errors
+            0x94 => Category::CheckingError,
+            _ => Category::Unknown,
+        }
+    }
+
+    pub fn description(&self) -> String {
+        let description = match self.kind {
+            StatusKind::ResponseAvailable { length } => return format!("{} bytes still available", length),
+            StatusKind::WrongLengthLe { length } => return format!("Wrong Le, {} bytes available", length),
+            StatusKind::WrongPin { tries_remaining } => return format!("Wrong PIN, {} tries remaining", tries_remaining),
+            StatusKind::Ok => "Process completed normally",
+            StatusKind::NoInformationWarning => "No information given, state of non-volatile memory unchanged",
+            StatusKind::ReturnedDataCorrupted => "Part of returned data may be corrupted",
+            StatusKind::EndOfFileReached => "End of file or record reached before reading Le bytes",
+            StatusKind::SelectedFileInvalidated => "Selected file invalidated",
+            StatusKind::FileControlInformationNotFormatted => "FCI not formatted according to ISO 7816-4",
+            StatusKind::SelectedFileTerminated => "Selected file in termination state",
+            StatusKind::NoInputDataFromSensor => "No input data available from a sensor on the card",
+            StatusKind::MemoryChangedWarning => "No information given, state of non-volatile memory changed",
+            StatusKind::FileFilledUp => "File filled up by the last write",
+            StatusKind::ExecutionError => "Execution error, state of non-volatile memory unchanged",
+            StatusKind::ImmediateResponseRequired => "Immediate response required by the card",
+            StatusKind::MemoryChangedError => "Execution error, state of non-volatile memory changed",
+            StatusKind::MemoryFailure => "Memory failure",
+            StatusKind::SecurityError => "Security-related issue",
+            StatusKind::WrongLength => "Wrong length",
+            StatusKind::ClassFunctionNotSupported => "Functions in CLA not supported",
+            StatusKind::LogicalChannelNotSupported => "Logical channel not supported",
+            StatusKind::SecureMessagingNotSupported => "Secure messaging not supported",
+            StatusKind::LastCommandOfChainExpected => "Last command of the chain expected",
+            StatusKind::CommandChainingNotSupported => "Command chaining not supported",
+            StatusKind::CommandNotAllowed => "Command not allowed, no information given",
+            StatusKind::IncompatibleFileStructure => "Command incompatible with file structure",
+            StatusKind::SecurityConditionNotSatisfied => "Security status not satisfied",
+            StatusKind::PinBlocked => "PIN blocked",
+            StatusKind::ReferencedDataInvalidated => "Referenced data invalidated",
+            StatusKind::ConditionsOfUseNotSatisfied => "Conditions of use not satisfied",
+            StatusKind::NoCurrentElementaryFile => "Command not allowed, no current EF",
+            StatusKind::SecureMessagingDataMissing => "Expected secure messaging data objects missing",
+            StatusKind::SecureMessagingDataIncorrect => "Incorrect secure messaging data objects",
+            StatusKind::WrongParameters => "Wrong parameters P1-P2, no information given",
+            StatusKind::IncorrectData => "Incorrect parameters in the data field",
+            StatusKind::FunctionNotSupported => "Function not supported",
+            StatusKind::FileNotFound => "File or application not found",
+            StatusKind::RecordNotFound => "Record not found",
+            StatusKind::NotEnoughMemory => "Not enough memory space in the file",
+            StatusKind::DataInconsistentWithTlv => "Lc inconsistent with TLV structure",
+            StatusKind::IncorrectParameters => "Incorrect parameters P1-P2",
+            StatusKind::DataInconsistentWithParameters => "Lc inconsistent with P1-P2",
+            StatusKind::ReferencedDataNotFound => "Referenced data or reference data not found",
+            StatusKind::FileAlreadyExists => "File already exists",
+            StatusKind::DedicatedFileNameAlreadyExists => "DF name already exists",
+            StatusKind::WrongParametersOffset => "Wrong parameters P1-P2, offset outside the EF",
+            StatusKind::InstructionCodeNotSupported => "Instruction code not supported or invalid",
+            StatusKind::ClassNotSupported => "Class not supported",
+            StatusKind::NoPreciseDiagnosis => "No precise diagnosis",
+            StatusKind::NoElementaryFileSelected => "No EF selected",
+            StatusKind::AddressOutOfRange => "Out of range, invalid address",
+            StatusKind::FileIdentifierNotFound => "File ID or pattern not found",
+            StatusKind::FileInconsistentWithCommand => "File inconsistent with the command",
+            StatusKind::Unknown => "Unknown status",
+        };
+        description.to_string()
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Category::Normal => "normal processing",
+            Category::Warning => "warning",
+            Category::ExecutionError => "execution error",
+            Category::CheckingError => "checking error",
+            Category::Unknown => "unknown category",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X}{:02X} {} ({})", self.sw1, self.sw2, self.description(), self.category())
+    }
 }
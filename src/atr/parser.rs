@@ -0,0 +1,166 @@
+use std::fmt;
+
+const FI_TABLE: [Option<u16>; 16] = [
+    Some(372), Some(372), Some(558), Some(744), Some(1116), Some(1488), Some(1860), None,
+    None, Some(512), Some(768), Some(1024), Some(1536), Some(2048), None, None,
+];
+
+const DI_TABLE: [Option<u8>; 16] = [
+    None, Some(1), Some(2), Some(4), Some(8), Some(16), Some(32), Some(64),
+    Some(12), Some(20), None, None, None, None, None, None,
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Convention {
+    Direct,
+    Inverse,
+}
+
+/// Interface bytes TAi, TBi, TCi and TDi of a single level `i`
+#[derive(Debug, Default)]
+pub struct InterfaceBytes {
+    pub ta: Option<u8>,
+    pub tb: Option<u8>,
+    pub tc: Option<u8>,
+    pub td: Option<u8>,
+}
+
+/// Answer To Reset as described in ISO 7816-3 §8.2
+#[derive(Debug)]
+pub struct ATR {
+    pub raw: Vec<u8>,
+    pub convention: Convention,
+    pub t0: u8,
+    pub interface: Vec<InterfaceBytes>,
+    pub historical: Vec<u8>,
+    pub tck: Option<u8>,
+}
+
+impl ATR {
+    pub fn parse(data: &[u8]) -> Result<ATR, &'static str> {
+        if data.len() < 2 {
+            return Err("Not enough data to parse ATR!");
+        }
+
+        let convention = match data[0] {
+            0x3B => Convention::Direct,
+            0x3F => Convention::Inverse,
+            _ => return Err("Invalid ATR initial character TS!"),
+        };
+
+        let t0 = data[1];
+        let mut position = 2;
+        let mut indicator = t0;
+        let mut interface = Vec::new();
+
+        loop {
+            let mut bytes = InterfaceBytes::default();
+            for (mask, field) in [(0x10, &mut bytes.ta), (0x20, &mut bytes.tb), (0x40, &mut bytes.tc), (0x80, &mut bytes.td)] {
+                if indicator & mask != 0 {
+                    *field = Some(*data.get(position).ok_or("ATR interface bytes are truncated!")?);
+                    position += 1;
+                }
+            }
+            let next = bytes.td;
+            interface.push(bytes);
+            match next {
+                Some(td) => indicator = td,
+                None => break,
+            }
+        }
+
+        let historical_length = usize::from(t0 & 0x0F);
+        let historical = data.get(position..position + historical_length)
+            .ok_or("ATR historical bytes are truncated!")?
+            .to_vec();
+        position += historical_length;
+
+        let mut atr = ATR { raw: data.to_vec(), convention, t0, interface, historical, tck: None };
+        // TCK follows whenever a protocol other than T=0 is indicated, T=15 included (ISO 7816-3 §8.2.5)
+        if atr.interface.iter().filter_map(|bytes| bytes.td).any(|td| td & 0x0F != 0) {
+            atr.tck = Some(*data.get(position).ok_or("ATR check byte TCK is missing!")?);
+        }
+        Ok(atr)
+    }
+
+    /// Transmission protocols indicated by the TDi bytes, T=0 being implicit when TD1 is absent. T=15, which only
+    /// announces global interface bytes, is left out.
+    pub fn protocols(&self) -> Vec<u8> {
+        let mut protocols: Vec<u8> = self.interface.iter()
+            .filter_map(|bytes| bytes.td)
+            .map(|td| td & 0x0F)
+            .filter(|protocol| *protocol != 0x0F)
+            .collect();
+        if protocols.is_empty() {
+            protocols.push(0);
+        }
+        protocols.dedup();
+        protocols
+    }
+
+    /// Exclusive-or of every byte from T0 up to and including TCK must be zero
+    pub fn is_tck_valid(&self) -> Option<bool> {
+        self.tck.map(|_| self.raw[1..].iter().fold(0, |acc, byte| acc ^ byte) == 0)
+    }
+
+    /// Clock rate conversion integer Fi and baud rate adjustment integer Di from TA1
+    pub fn fi_di(&self) -> Option<(Option<u16>, Option<u8>)> {
+        self.interface[0].ta.map(|ta1| {
+            (FI_TABLE[usize::from(ta1 >> 4)], DI_TABLE[usize::from(ta1 & 0x0F)])
+        })
+    }
+}
+
+fn describe(level: usize, name: &str, value: u8, atr: &ATR) -> String {
+    // Interface bytes beyond the second level are specific to the protocol announced by the previous TD
+    let protocol = atr.interface.get(level.wrapping_sub(2)).and_then(|bytes| bytes.td).map(|td| td & 0x0F);
+    let meaning = match (name, level) {
+        ("TA", 1) => {
+            let (fi, di) = atr.fi_di().unwrap_or((None, None));
+            let show = |value: Option<String>| value.unwrap_or_else(|| String::from("RFU"));
+            format!("Fi={} Di={}", show(fi.map(|fi| fi.to_string())), show(di.map(|di| di.to_string())))
+        }
+        ("TC", 1) => format!("Extra guard time N={}", value),
+        ("TC", 2) => format!("Work waiting time WI={}", value),
+        ("TA", 2) => format!("Specific mode T={}{}", value & 0x0F, if value & 0x10 != 0 { ", implicit Fi/Di" } else { "" }),
+        ("TD", _) if value & 0x0F == 0x0F => String::from("Global interface bytes follow"),
+        ("TD", _) => format!("Protocol T={}", value & 0x0F),
+        ("TA", _) if level > 2 && protocol == Some(1) => format!("IFSC={}", value),
+        ("TB", _) if level > 2 && protocol == Some(1) => format!("BWI={} CWI={}", value >> 4, value & 0x0F),
+        ("TC", _) if level > 2 && protocol == Some(1) => format!("Error detection {}", if value & 0x01 != 0 { "CRC" } else { "LRC" }),
+        ("TA", _) if level > 2 && protocol == Some(15) => format!("Clock stop X={} Class Y={:02X}", value >> 6, value & 0x3F),
+        _ => String::new(),
+    };
+    match meaning.is_empty() {
+        true => format!("    > {}{} = 0x{:02X}\n", name, level, value),
+        false => format!("    > {}{} = 0x{:02X} | {}\n", name, level, value, meaning),
+    }
+}
+
+impl fmt::Display for ATR {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw_str: Vec<String> = self.raw.iter().map(|a| format!("{:02X}", a)).collect();
+        let historical_str: Vec<String> = self.historical.iter().map(|a| format!("{:02X}", a)).collect();
+        let protocols_str: Vec<String> = self.protocols().iter().map(|t| format!("T={}", t)).collect();
+
+        writeln!(f, "ATR: 0x{}", raw_str.join(""))?;
+        writeln!(f, "  TS: 0x{:02X} | {:?} convention", self.raw[0], self.convention)?;
+        writeln!(f, "  T0: 0x{:02X} | {} historical bytes", self.t0, self.t0 & 0x0F)?;
+        writeln!(f, "  Interface bytes: [")?;
+        for (index, bytes) in self.interface.iter().enumerate() {
+            for (name, value) in [("TA", bytes.ta), ("TB", bytes.tb), ("TC", bytes.tc), ("TD", bytes.td)] {
+                if let Some(value) = value {
+                    write!(f, "{}", describe(index + 1, name, value, self))?;
+                }
+            }
+        }
+        writeln!(f, "  ]")?;
+        writeln!(f, "  Protocols: {}", protocols_str.join(", "))?;
+        writeln!(f, "  Historical bytes: 0x{}", historical_str.join(""))?;
+        match (self.tck, self.is_tck_valid()) {
+            (Some(tck), Some(true)) => writeln!(f, "  TCK: 0x{:02X} | valid", tck),
+            (Some(tck), _) => writeln!(f, "  TCK: 0x{:02X} | INVALID", tck),
+            (None, _) => writeln!(f, "  TCK: absent"),
+        }
+    }
+}
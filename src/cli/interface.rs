@@ -1,17 +1,48 @@
 use std::path::PathBuf;
 
-use hex::FromHex;
+use pcsc::{Protocols, ShareMode};
 use structopt::StructOpt;
 
-use crate::apdu::capdu::CryptogramType;
-use crate::utils::extension::Hexadecimal;
+use emv::apdu::capdu;
+use emv::apdu::capdu::{APDU, CryptogramType};
+use emv::connection::ResetType;
+use emv::connection::usb::Settings;
+use emv::error::EmvError;
 
 #[derive(StructOpt)]
 pub struct Emv {
+    /// Protocol to negotiate with the card: T0, T1 or ANY
+    #[structopt(long, default_value = "ANY", parse(try_from_str = parse_protocols))]
+    pub protocol: Protocols,
+    /// Connect to the card in exclusive mode instead of sharing it with other applications
+    #[structopt(long)]
+    pub exclusive: bool,
+    /// Drive a remote virtual card (vicc) waiting for a reader at this address instead of a PC/SC reader
+    #[structopt(long)]
+    pub remote: Option<String>,
+    /// Terminal profile (TOML) describing the terminal to emulate, instead of an attended online terminal in Brazil
+    #[structopt(long, parse(from_os_str))]
+    pub terminal: Option<PathBuf>,
     #[structopt(subcommand)]
     pub mode: Mode,
 }
 
+impl Emv {
+    pub fn settings(&self) -> Settings {
+        let share_mode = if self.exclusive { ShareMode::Exclusive } else { ShareMode::Shared };
+        Settings { share_mode, protocols: self.protocol }
+    }
+}
+
+fn parse_protocols(str: &str) -> Result<Protocols, String> {
+    match str.to_uppercase().as_str() {
+        "T0" | "T=0" => Ok(Protocols::T0),
+        "T1" | "T=1" => Ok(Protocols::T1),
+        "ANY" => Ok(Protocols::ANY),
+        _ => Err(format!("Unknown protocol {}", str))
+    }
+}
+
 #[derive(StructOpt)]
 pub enum Mode {
     Shell,
@@ -19,6 +50,15 @@ pub enum Mode {
         #[structopt(parse(from_os_str))]
         input: PathBuf
     },
+    /// Exposes the simulated card to a vpcd reader, making it available to any PC/SC application on that host
+    Serve {
+        /// Address of the vpcd reader, or the address to listen on with --listen
+        #[structopt(default_value = "localhost:35963")]
+        address: String,
+        /// Wait for the vpcd reader to connect instead of connecting to it
+        #[structopt(long)]
+        listen: bool,
+    },
 }
 
 #[derive(Debug)]
@@ -32,8 +72,9 @@ pub enum Command {
         sfi: u8,
     },
     GenerateAC {
-        cryptogram_type: CryptogramType,
+        cryptogram_type: Option<CryptogramType>,
         cdol: Option<Vec<u8>>,
+        cda: bool,
     },
     PutData {
         tag: u16,
@@ -42,54 +83,186 @@ pub enum Command {
     GetData {
         tag: u16
     },
+    TransactionLog {
+        log_entry: Option<Vec<u8>>
+    },
+    Counters {
+        proprietary: Vec<u16>
+    },
     Verify {
         pin: Vec<u8>
     },
+    ExternalAuthenticate {
+        issuer_authentication_data: Vec<u8>
+    },
+    OfflineChangePin {
+        pin: Vec<u8>
+    },
+    GetChallenge,
+    InternalAuthenticate {
+        ddol: Option<Vec<u8>>
+    },
+    ComputeCryptographicChecksum {
+        udol: Vec<u8>
+    },
+    ApplicationBlock,
+    ApplicationUnblock,
+    PinStatus,
     PinUnblock,
     PinChange {
         pin: Vec<u8>
-    }
+    },
+    Send {
+        apdu: APDU
+    },
+    Transaction {
+        aid: Option<Vec<u8>>,
+        step: bool,
+    },
+    CheckRestrictions,
+    State,
+    Set {
+        tag: u16,
+        value: Vec<u8>,
+    },
+    Atr,
+    Reset {
+        reset_type: ResetType
+    },
+    Reconnect,
+    Begin,
+    End,
 }
 
 impl Command {
-    pub fn from_str(str: String) -> Result<Command, String> {
+    /// Whether the command re-establishes the connection, which can't happen during a transaction
+    pub fn controls_connection(&self) -> bool {
+        matches!(self, Command::Reset { .. } | Command::Reconnect)
+    }
+
+    pub fn from_str(str: String) -> Result<Command, EmvError> {
         let parts: Vec<&str> = str.trim().split(' ').collect();
         let name = parts[0].to_lowercase();
 
         match name.as_str() {
             "select" => Ok(Command::Select {
-                application: parts[1].to_vec_u8()
+                application: hex_argument(&parts, 1, "AID")?
             }),
             "get_processing_options" => Ok(Command::GetProcessingOptions),
             "generate_ac" => {
-                let mut cdol = None;
-                if parts.len() > 2 {
-                    cdol = Some(parts[2].to_vec_u8());
-                }
+                // `cda` can come anywhere, the type and CDOL keeping their order without it
+                let arguments: Vec<&str> = parts[1..].iter().filter(|part| !part.eq_ignore_ascii_case("cda")).copied().collect();
+                // Terminal action analysis decides on the cryptogram type when it isn't provided
                 Ok(Command::GenerateAC {
-                    cryptogram_type: CryptogramType::from_str(parts[1]),
-                    cdol,
+                    cryptogram_type: arguments.first().filter(|part| !part.is_empty()).map(|part| part.parse()).transpose().map_err(EmvError::Command)?,
+                    cdol: arguments.get(1).map(|part| parse_hex(part, "CDOL data")).transpose()?,
+                    cda: parts[1..].iter().any(|part| part.eq_ignore_ascii_case("cda")),
                 })
             }
             "get_data" => Ok(Command::GetData {
-                tag: parts[1].to_u16()
+                tag: tag_argument(&parts, 1, "tag")?
+            }),
+            "atc" => Ok(Command::GetData { tag: 0x9F36 }),
+            "last_online_atc" => Ok(Command::GetData { tag: 0x9F13 }),
+            "log_format" => Ok(Command::GetData { tag: 0x9F4F }),
+            "transaction_log" => Ok(Command::TransactionLog {
+                log_entry: parts.get(1).filter(|part| !part.is_empty()).map(|part| parse_hex(part, "log entry")).transpose()?
+            }),
+            "counters" => Ok(Command::Counters {
+                proprietary: parts[1..].iter().filter(|part| !part.is_empty()).map(|part| parse_tag(part, "tag")).collect::<Result<_, _>>()?
             }),
             "put_data" => Ok(Command::PutData {
-                tag: parts[1].to_u16(),
-                value: parts[2].to_vec_u8(),
+                tag: tag_argument(&parts, 1, "tag")?,
+                value: hex_argument(&parts, 2, "value")?,
             }),
             "read_record" => Ok(Command::ReadRecord {
-                record: parts[1].to_u8(),
-                sfi: parts[2].to_u8(),
+                record: byte_argument(&parts, 1, "record")?,
+                sfi: byte_argument(&parts, 2, "SFI")?,
             }),
             "verify" => Ok(Command::Verify {
-                pin: parts[1].to_vec_u8()
+                pin: capdu::pin_block(argument(&parts, 1, "PIN")?).map_err(|err| EmvError::Command(String::from(err)))?
+            }),
+            "external_authenticate" => {
+                let data = hex_argument(&parts, 1, "issuer authentication data")?;
+                // ARPC followed by proprietary data or the ARC, as in tag 91
+                if !(8..=16).contains(&data.len()) {
+                    return Err(EmvError::Command(String::from("Issuer authentication data must be 8 to 16 bytes long")));
+                }
+                Ok(Command::ExternalAuthenticate { issuer_authentication_data: data })
+            }
+            "offline_change_pin" => Ok(Command::OfflineChangePin {
+                pin: hex_argument(&parts, 1, "PIN")?
+            }),
+            "get_challenge" => Ok(Command::GetChallenge),
+            "internal_authenticate" => Ok(Command::InternalAuthenticate {
+                ddol: parts.get(1).filter(|part| !part.is_empty()).map(|part| parse_hex(part, "DDOL data")).transpose()?
+            }),
+            "compute_cryptographic_checksum" => Ok(Command::ComputeCryptographicChecksum {
+                udol: hex_argument(&parts, 1, "UDOL data")?
             }),
+            "application_block" => Ok(Command::ApplicationBlock),
+            "application_unblock" => Ok(Command::ApplicationUnblock),
+            "pin_status" => Ok(Command::PinStatus),
             "pin_unblock" => Ok(Command::PinUnblock),
             "pin_change" => Ok(Command::PinChange {
-                pin: parts[1].to_vec_u8()
+                pin: hex_argument(&parts, 1, "PIN")?
             }),
-            _ => Err(name)
+            // The hex string may be split in groups, as APDUs are usually written
+            "send" | "apdu" => {
+                let raw = hex::decode(parts[1..].concat()).map_err(|_| EmvError::Parse("The C-APDU must be written as pairs of hex digits!"))?;
+                Ok(Command::Send { apdu: APDU::parse(&raw).map_err(EmvError::Parse)? })
+            }
+            // The AID defaults to the selected application, and `step` waits for the user before each phase
+            "transaction" => Ok(Command::Transaction {
+                aid: parts[1..].iter().find(|part| !part.is_empty() && !part.eq_ignore_ascii_case("step")).map(|part| parse_hex(part, "AID")).transpose()?,
+                step: parts[1..].iter().any(|part| part.eq_ignore_ascii_case("step")),
+            }),
+            "check_restrictions" => Ok(Command::CheckRestrictions),
+            "state" => Ok(Command::State),
+            "set" => Ok(Command::Set {
+                tag: tag_argument(&parts, 1, "tag")?,
+                value: hex_argument(&parts, 2, "value")?,
+            }),
+            "atr" => Ok(Command::Atr),
+            "reset" => Ok(Command::Reset {
+                reset_type: parts.get(1).unwrap_or(&"warm").parse().map_err(EmvError::Command)?
+            }),
+            "reconnect" => Ok(Command::Reconnect),
+            "begin" => Ok(Command::Begin),
+            "end" => Ok(Command::End),
+            _ => Err(EmvError::Command(format!("Unknown command {}", name)))
         }
     }
 }
+
+fn argument<'a>(parts: &[&'a str], index: usize, name: &str) -> Result<&'a str, EmvError> {
+    parts.get(index).copied().filter(|part| !part.is_empty()).ok_or_else(|| EmvError::Command(format!("Missing {}", name)))
+}
+
+fn hex_argument(parts: &[&str], index: usize, name: &str) -> Result<Vec<u8>, EmvError> {
+    parse_hex(argument(parts, index, name)?, name)
+}
+
+fn byte_argument(parts: &[&str], index: usize, name: &str) -> Result<u8, EmvError> {
+    match hex_argument(parts, index, name)?.as_slice() {
+        [byte] => Ok(*byte),
+        _ => Err(EmvError::Command(format!("The {} must be one byte long", name))),
+    }
+}
+
+fn tag_argument(parts: &[&str], index: usize, name: &str) -> Result<u16, EmvError> {
+    parse_tag(argument(parts, index, name)?, name)
+}
+
+fn parse_hex(part: &str, name: &str) -> Result<Vec<u8>, EmvError> {
+    hex::decode(part).map_err(|_| EmvError::Command(format!("The {} must be written as pairs of hex digits", name)))
+}
+
+/// One or two byte tags, as the session keeps them
+fn parse_tag(part: &str, name: &str) -> Result<u16, EmvError> {
+    match parse_hex(part, name)?.as_slice() {
+        [byte] => Ok(u16::from(*byte)),
+        [first, second] => Ok(u16::from_be_bytes([*first, *second])),
+        _ => Err(EmvError::Command(format!("The {} must be one or two bytes long", name))),
+    }
+}
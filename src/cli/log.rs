@@ -0,0 +1,76 @@
+use emv::controller::TransactionLog;
+use emv::tlv::dol;
+
+/// Columns of the table, each showing a data object of the log records
+const COLUMNS: [(&str, u16); 7] = [
+    ("Date", 0x9A),
+    ("Time", 0x9F21),
+    ("Amount", 0x9F02),
+    ("Currency", 0x5F2A),
+    ("Country", 0x9F1A),
+    ("ATC", 0x9F36),
+    ("CID", 0x9F27),
+];
+
+/// Renders the transaction log as a table, data objects missing from the Log Format being shown as `-`
+pub fn print(log: &TransactionLog) {
+    if log.records.is_empty() {
+        println!("The transaction log is empty");
+        return;
+    }
+    let header: Vec<String> = COLUMNS.iter().map(|(name, _)| name.to_string()).collect();
+    let mut rows = vec![header];
+    for record in &log.records {
+        match dol::split(&log.format, record) {
+            Ok(values) => rows.push(COLUMNS.iter()
+                .map(|(_, tag)| {
+                    values.iter()
+                        .find(|(entry, _)| entry.tag == *tag)
+                        .map_or(String::from("-"), |(_, value)| format_value(*tag, value))
+                })
+                .collect()),
+            Err(err) => eprintln!("Failed to parse log record {:02X?}: {}", record, err),
+        }
+    }
+
+    let widths: Vec<usize> = (0..COLUMNS.len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for (index, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:width$}", cell, width = width)).collect();
+        println!("| {} |", cells.join(" | "));
+        if index == 0 {
+            let separators: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+            println!("|-{}-|", separators.join("-|-"));
+        }
+    }
+}
+
+fn format_value(tag: u16, value: &[u8]) -> String {
+    let digits = hex::encode_upper(value);
+    // Values whose length doesn't match their format are shown as they are
+    match (tag, value.len()) {
+        // YYMMDD and HHMMSS
+        (0x9A, 3) => format!("20{}-{}-{}", &digits[0..2], &digits[2..4], &digits[4..6]),
+        (0x9F21, 3) => format!("{}:{}:{}", &digits[0..2], &digits[2..4], &digits[4..6]),
+        // n12 with two implied decimals
+        (0x9F02, _) => {
+            let amount = digits.trim_start_matches('0');
+            let amount = format!("{:0>3}", amount);
+            format!("{}.{}", &amount[..amount.len() - 2], &amount[amount.len() - 2..])
+        }
+        // Numeric ISO 4217 and ISO 3166 codes
+        (0x5F2A, 2) | (0x9F1A, 2) => String::from(&digits[1..]),
+        (0x9F36, _) => value.iter().fold(0_u32, |atc, byte| atc << 8 | u32::from(*byte)).to_string(),
+        (0x9F27, 1) => {
+            let cryptogram_type = match value[0] & 0xC0 {
+                0x00 => "AAC",
+                0x40 => "TC",
+                0x80 => "ARQC",
+                _ => "RFU",
+            };
+            format!("{} ({})", digits, cryptogram_type)
+        }
+        _ => digits,
+    }
+}
@@ -1,31 +1,56 @@
 use std::io;
 use std::io::Write;
 
+use emv::error::EmvError;
+
 use crate::cli::interface::Command;
 
 mod banner;
 pub mod interface;
+pub mod log;
+pub mod output;
+pub mod prompt;
+pub mod trace;
 
 pub fn announcement() {
     println!("{}", banner::BANNER);
 }
 
-pub fn read_command() -> Option<Command> {
-    let mut buffer = String::new();
-    print!("> ");
-    io::stdout().flush();
-    io::stdin().read_line(&mut buffer);
-    let input = buffer.trim();
-    match input {
-        "\n" => None,
-        _ => Command::from_str(String::from(input)).ok(),
+/// Reads the next command, skipping empty lines, none once the input is over
+pub fn read_command() -> Option<Result<Command, EmvError>> {
+    loop {
+        let mut buffer = String::new();
+        print!("> ");
+        io::stdout().flush().ok()?;
+        if io::stdin().read_line(&mut buffer).ok()? == 0 {
+            return None;
+        }
+        let input = buffer.trim();
+        if !input.is_empty() {
+            return Some(Command::from_str(String::from(input)));
+        }
     }
 }
 
-pub fn read_hex_input(question: &'static str) -> Vec<u8> {
+/// Waits for the user before going on, returning false when they quit
+pub fn proceed(question: String) -> bool {
+    let mut buffer = String::new();
+    print!("{}", question);
+    io::stdout().flush().expect("Unable to flush stdout");
+    io::stdin().read_line(&mut buffer).expect("Unable to read from stdin");
+    !matches!(buffer.trim().to_lowercase().as_str(), "q" | "quit")
+}
+
+/// Reads a line of input, trimmed
+pub fn read_line(question: &str) -> String {
     let mut buffer = String::new();
     print!("{}", question);
-    io::stdout().flush();
-    io::stdin().read_line(&mut buffer);
-    hex::decode(buffer.trim()).expect("Not a hex string")
+    io::stdout().flush().expect("Unable to flush stdout");
+    io::stdin().read_line(&mut buffer).expect("Unable to read from stdin");
+    String::from(buffer.trim())
+}
+
+/// Reads a line of hex input, a mistyped answer being reported as an invalid command
+pub fn read_hex_input(question: &'static str) -> Result<Vec<u8>, EmvError> {
+    hex::decode(read_line(question)).map_err(|_| EmvError::Command(String::from("The input must be written as pairs of hex digits")))
 }
@@ -0,0 +1,78 @@
+use emv::apdu::rapdu::RAPDU;
+use emv::atr::parser::ATR;
+use emv::connection::Transport;
+use emv::connection::vpcd::Event;
+use emv::error::EmvError;
+use emv::session::EmvSession;
+use emv::tlv::dol;
+use emv::tlv::parser::Tag;
+use emv::transaction::Step;
+
+/// Shows the parsed Answer To Reset and the protocol negotiated by the reader
+pub fn answer_to_reset(card: &dyn Transport, atr: Result<ATR, EmvError>) {
+    match atr {
+        Ok(atr) => println!("\n{}", atr),
+        Err(err) => eprintln!("Failed to parse ATR: {}", err),
+    }
+    println!("Negotiated protocol: {}", card.protocol());
+}
+
+/// Shows the value of a data object read with GET DATA, decoding the ones GET DATA is meant for and binary numbers
+/// being shown in decimal otherwise
+pub fn data_object(tag: u16, value: &[u8]) {
+    let number = value.iter().fold(0_u64, |number, byte| number << 8 | u64::from(*byte));
+    let description = match tag {
+        0x9F17 => format!("{} tries remaining", number),
+        0x9F4F => match dol::parse(value) {
+            Ok(entries) => entries.iter().map(|entry| format!("\n   > {}", entry)).collect(),
+            Err(err) => String::from(err),
+        },
+        _ if value.len() <= 8 => number.to_string(),
+        _ => hex::encode_upper(value),
+    };
+    println!("{} ({:02X}): {}", Tag::new(tag), tag, description);
+}
+
+pub fn cryptographic_checksum(response: &RAPDU) {
+    for (tag, track) in [(Tag::CardVerificationCode3Track1, 1), (Tag::CardVerificationCode3Track2, 2)] {
+        if let Some(tlv) = response.find(&tag) {
+            // The CVC3 is a binary number, whose decimal digits are copied to the discretionary data
+            let cvc3 = tlv.value().iter().fold(0_u64, |cvc3, byte| cvc3 << 8 | u64::from(*byte));
+            println!("CVC3 Track {}: {} ({})", track, hex::encode_upper(tlv.value()), cvc3);
+        }
+    }
+    if let Some(tlv) = response.find(&Tag::ApplicationTransactionCounter) {
+        println!("Application Transaction Counter: {}", hex::encode_upper(tlv.value()));
+    }
+}
+
+/// Shows the outcome of a transaction phase, along with the TVR and TSI bits it set
+pub fn step(step: &Step, session: &EmvSession) {
+    println!("\n=== {} ===", step.phase);
+    for note in &step.notes {
+        println!("{}", note);
+    }
+    for bit in &step.tvr {
+        println!("TVR + {}", bit);
+    }
+    for bit in &step.tsi {
+        println!("TSI + {}", bit);
+    }
+    println!("TVR: {} TSI: {}",
+             hex::encode_upper(session.terminal(0x95).unwrap_or_default()),
+             hex::encode_upper(session.terminal(0x9B).unwrap_or_default()));
+}
+
+/// Reports what the simulated card served to vpcd goes through
+pub fn served(event: Event) {
+    match event {
+        Event::Listening(address) => println!("Waiting for vpcd on {}", address),
+        Event::Connected(address) => println!("vpcd connected on {}", address),
+        Event::Disconnected => println!("vpcd disconnected"),
+        Event::Rejected(err) => eprintln!("{}", err),
+        Event::Exchanged(command, response) => {
+            println!("\nC-APDU: {:02X?}", command);
+            println!("R-APDU: {:02X?}", response);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use emv::session::EmvSession;
+use emv::transaction::Interaction;
+use emv::transaction::online::AuthorisationResponse;
+
+use crate::cli;
+
+/// Asks the user at the shell for what the transaction needs
+pub struct Prompt;
+
+impl Interaction for Prompt {
+    /// Reads the PIN without echoing it, an empty one bypassing the PIN entry
+    fn pin(&self, tries_remaining: Option<u8>) -> Option<String> {
+        let question = match tries_remaining {
+            Some(tries) => format!("Enter the PIN ({} tries remaining, empty to bypass): ", tries),
+            None => String::from("Enter the PIN (empty to bypass): "),
+        };
+        let pin = rpassword::prompt_password_stdout(&question).ok()?;
+        Some(pin).filter(|pin| !pin.is_empty())
+    }
+
+    /// Reads the response data objects in hex, an empty response standing for the approval of a host stub and `none`
+    /// for an issuer that can't be reached
+    fn authorise(&self, _session: &EmvSession) -> Option<AuthorisationResponse> {
+        loop {
+            let response = cli::read_line("Enter the authorisation response (8A, 91, 71 and 72 in hex, empty to approve, none when offline): ");
+            match response.to_lowercase().as_str() {
+                "" => return Some(AuthorisationResponse::approval()),
+                "none" => return None,
+                _ => {}
+            }
+            match hex::decode(&response).map_err(|_| "Not a hex string").and_then(|data| AuthorisationResponse::parse(&data)) {
+                Ok(response) => return Some(response),
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use emv::apdu::capdu::APDU;
+use emv::apdu::rapdu::RAPDU;
+use emv::connection::Transport;
+use emv::error::EmvError;
+
+/// Shows every APDU exchanged with the card, GET RESPONSE and retries included
+pub struct Trace<'a> {
+    card: &'a dyn Transport,
+}
+
+impl<'a> Trace<'a> {
+    pub fn new(card: &'a dyn Transport) -> Trace<'a> {
+        Trace { card }
+    }
+}
+
+impl Transport for Trace<'_> {
+    fn transceive(&self, command: &[u8]) -> Result<Vec<u8>, EmvError> {
+        match APDU::parse(command) {
+            Ok(apdu) => println!("\nC-APDU: {} ({}): {:02X?}", apdu.name, apdu.case(), command),
+            Err(_) => println!("\nC-APDU: {:02X?}", command),
+        }
+        let response = self.card.transceive(command)?;
+        match RAPDU::from_response(&response) {
+            Ok(rapdu) => println!("{}", rapdu),
+            Err(err) => eprintln!("{} {:02X?}", err, response),
+        }
+        Ok(response)
+    }
+
+    fn atr(&self) -> Result<Vec<u8>, EmvError> {
+        self.card.atr()
+    }
+
+    fn protocol(&self) -> &'static str {
+        self.card.protocol()
+    }
+}
@@ -1 +1,57 @@
+use std::str::FromStr;
+
+use crate::apdu::capdu::APDU;
+use crate::apdu::rapdu::RAPDU;
+use crate::error::EmvError;
+
+#[cfg(feature = "pcsc")]
 pub mod usb;
+pub mod vpcd;
+
+/// Exchanges bytes with a card, no matter how it is reached
+pub trait Transport {
+    /// Sends a raw C-APDU and returns the raw R-APDU, status words included
+    fn transceive(&self, command: &[u8]) -> Result<Vec<u8>, EmvError>;
+
+    fn atr(&self) -> Result<Vec<u8>, EmvError>;
+
+    /// Describes the transmission protocol in use
+    fn protocol(&self) -> &'static str;
+}
+
+/// Commands exchanged with the card while it is held exclusively
+pub type Exchange<'a> = Box<dyn FnOnce(&dyn Transport) + 'a>;
+
+/// Session with a card that can be reset, re-established or held exclusively
+pub trait Connection: Transport {
+    fn reset(&mut self, reset_type: &ResetType) -> Result<(), EmvError>;
+
+    fn reconnect(&mut self) -> Result<(), EmvError>;
+
+    /// Runs the exchange while no other application is able to talk to the card
+    fn transaction(&mut self, exchange: Exchange) -> Result<(), EmvError>;
+}
+
+#[derive(Debug)]
+pub enum ResetType {
+    Warm,
+    Cold,
+}
+
+impl FromStr for ResetType {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<ResetType, String> {
+        match str.to_lowercase().as_str() {
+            "warm" => Ok(ResetType::Warm),
+            "cold" => Ok(ResetType::Cold),
+            _ => Err(format!("Unknown reset type {}", str))
+        }
+    }
+}
+
+/// Sends the C-APDU and splits the card's answer into its data and status words
+pub fn transmit(card: &dyn Transport, apdu: &APDU) -> Result<RAPDU, EmvError> {
+    let response = card.transceive(&apdu.to_array())?;
+    RAPDU::from_response(&response).map_err(EmvError::Parse)
+}
@@ -1,73 +1,109 @@
 use pcsc::*;
 
-use crate::apdu::capdu::APDU;
-use crate::apdu::rapdu::{RAPDU, Status};
-
-pub fn transmit(card: &Card, apdu: &APDU) -> Result<RAPDU, &'static str> {
-    let mut buffer = [0; MAX_BUFFER_SIZE];
-    println!("\nC-APDU: {}: {:02X?}", apdu.name, apdu.to_array());
-    match card.transmit(&apdu.to_array(), &mut buffer) {
-        Ok(response) => {
-            let rapdu;
-            let length = response.len();
-            // Header Status
-            if length == 2 {
-                rapdu = RAPDU::new(Status::new(response[0], response[1]), &response[2..]);
-            }
-            // Trailing Status
-            else {
-                rapdu = RAPDU::new(Status::new(response[length - 2], response[length - 1]), &response[0..length - 2]);
-            }
-            println!("{}", rapdu);
-            Ok(rapdu)
-        }
-        Err(err) => {
-            eprintln!("Failed to transmit APDU command to card: {}", err);
-            Err("Error transmitting command")
-        }
+use crate::connection::{Connection, Exchange, ResetType, Transport};
+use crate::error::EmvError;
+
+/// How the connection with the card is established and re-established
+pub struct Settings {
+    pub share_mode: ShareMode,
+    pub protocols: Protocols,
+}
+
+/// Card inserted in a PC/SC reader
+pub struct Reader {
+    card: Card,
+    settings: Settings,
+    name: String,
+}
+
+impl Reader {
+    /// Name of the reader, as listed by PC/SC
+    pub fn name(&self) -> &str {
+        &self.name
     }
 }
 
-pub fn connect() -> Option<Card> {
-    // Establish a context
-    let context = match Context::establish(Scope::User) {
-        Ok(ctx) => ctx,
-        Err(err) => {
-            eprintln!("Failed to establish context: {}", err);
-            std::process::exit(1);
-        }
-    };
+impl Transport for Card {
+    fn transceive(&self, command: &[u8]) -> Result<Vec<u8>, EmvError> {
+        let mut buffer = [0; MAX_BUFFER_SIZE];
+        self.transmit(command, &mut buffer)
+            .map(|response| response.to_vec())
+            .map_err(|err| EmvError::transport("Failed to transmit APDU command to card", err))
+    }
 
-    // List available readers.
-    let mut readers_buf = [0; 2048];
-    let mut readers = match context.list_readers(&mut readers_buf) {
-        Ok(readers) => readers,
-        Err(err) => {
-            eprintln!("Failed to list readers: {}", err);
-            std::process::exit(1);
-        }
-    };
-
-    // Use the first reader.
-    let reader = match readers.next() {
-        Some(reader) => reader,
-        None => {
-            println!("No readers are connected.");
-            return None;
-        }
-    };
-    println!("Using reader: {:?}", reader);
-
-    // Connect to the card and return it.
-    match context.connect(reader, ShareMode::Shared, Protocols::ANY) {
-        Ok(card) => Some(card),
-        Err(Error::NoSmartcard) => {
-            println!("A smartcard is not present in the reader.");
-            return None;
-        }
-        Err(err) => {
-            eprintln!("Failed to connect to card: {}", err);
-            std::process::exit(1);
+    fn atr(&self) -> Result<Vec<u8>, EmvError> {
+        let mut names_buffer = [0; 2048];
+        let mut atr_buffer = [0; MAX_ATR_SIZE];
+        self.status2(&mut names_buffer, &mut atr_buffer)
+            .map(|status| status.atr().to_vec())
+            .map_err(|err| EmvError::transport("Failed to read card status", err))
+    }
+
+    fn protocol(&self) -> &'static str {
+        let mut names_buffer = [0; 2048];
+        let mut atr_buffer = [0; MAX_ATR_SIZE];
+        match self.status2(&mut names_buffer, &mut atr_buffer).map(|status| status.protocol2()) {
+            Ok(Some(Protocol::T0)) => "T=0",
+            Ok(Some(Protocol::T1)) => "T=1",
+            Ok(Some(Protocol::RAW)) => "RAW",
+            Ok(None) => "None",
+            Err(_) => "Unknown",
         }
     }
 }
+
+impl Transport for Reader {
+    fn transceive(&self, command: &[u8]) -> Result<Vec<u8>, EmvError> {
+        self.card.transceive(command)
+    }
+
+    fn atr(&self) -> Result<Vec<u8>, EmvError> {
+        self.card.atr()
+    }
+
+    fn protocol(&self) -> &'static str {
+        self.card.protocol()
+    }
+}
+
+impl Connection for Reader {
+    fn reset(&mut self, reset_type: &ResetType) -> Result<(), EmvError> {
+        let disposition = match reset_type {
+            ResetType::Warm => Disposition::ResetCard,
+            ResetType::Cold => Disposition::UnpowerCard
+        };
+        reconnect(&mut self.card, &self.settings, disposition)
+    }
+
+    fn reconnect(&mut self) -> Result<(), EmvError> {
+        reconnect(&mut self.card, &self.settings, Disposition::LeaveCard)
+    }
+
+    fn transaction(&mut self, exchange: Exchange) -> Result<(), EmvError> {
+        let transaction = self.card.transaction().map_err(|err| EmvError::transport("Failed to begin transaction", err))?;
+        exchange(&*transaction);
+        transaction.end(Disposition::LeaveCard).map_err(|(_, err)| EmvError::transport("Failed to end transaction", err))
+    }
+}
+
+/// Connects to the card in the first PC/SC reader
+pub fn connect(settings: Settings) -> Result<Reader, EmvError> {
+    let context = Context::establish(Scope::User).map_err(|err| EmvError::transport("Failed to establish context", err))?;
+
+    let mut readers_buf = [0; 2048];
+    let reader = context.list_readers(&mut readers_buf)
+        .map_err(|err| EmvError::transport("Failed to list readers", err))?
+        .next()
+        .ok_or_else(|| EmvError::transport("No readers are connected", Error::NoReadersAvailable))?;
+    let name = reader.to_string_lossy().into_owned();
+
+    let card = context.connect(reader, settings.share_mode, settings.protocols)
+        .map_err(|err| EmvError::transport("Failed to connect to card", err))?;
+    Ok(Reader { card, settings, name })
+}
+
+/// Re-establishes the connection with the card, resetting or powering it down according to the disposition
+fn reconnect(card: &mut Card, settings: &Settings, disposition: Disposition) -> Result<(), EmvError> {
+    card.reconnect(settings.share_mode, settings.protocols, disposition)
+        .map_err(|err| EmvError::transport("Failed to reconnect to card", err))
+}
@@ -0,0 +1,147 @@
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::connection::{Connection, Exchange, ResetType, Transport};
+use crate::error::EmvError;
+use crate::simulator::card::SimulatedCard;
+
+// Control messages of the vsmartcard vpcd/vicc protocol, any other message is an APDU
+const POWER_OFF: u8 = 0x00;
+const POWER_ON: u8 = 0x01;
+const RESET: u8 = 0x02;
+const GET_ATR: u8 = 0x04;
+
+/// Remote virtual card (vicc) driven through the vpcd protocol
+pub struct Client {
+    stream: TcpStream,
+}
+
+/// What happens while the simulated card is served, for the caller to report
+pub enum Event<'a> {
+    Listening(&'a str),
+    Connected(&'a str),
+    Disconnected,
+    /// A reader failed to connect, while listening goes on
+    Rejected(EmvError),
+    /// C-APDU received and the R-APDU answering it, status words included
+    Exchanged(&'a [u8], &'a [u8]),
+}
+
+impl Client {
+    fn control(&self, code: u8) -> Result<(), EmvError> {
+        send(&self.stream, &[code]).map_err(|err| EmvError::transport("Failed to send control message to remote card", err))
+    }
+
+    fn request(&self, message: &[u8]) -> Result<Vec<u8>, EmvError> {
+        send(&self.stream, message)
+            .and_then(|_| receive(&self.stream))
+            .map_err(|err| EmvError::transport("Failed to exchange message with remote card", err))
+    }
+}
+
+impl Transport for Client {
+    fn transceive(&self, command: &[u8]) -> Result<Vec<u8>, EmvError> {
+        self.request(command)
+    }
+
+    fn atr(&self) -> Result<Vec<u8>, EmvError> {
+        self.request(&[GET_ATR])
+    }
+
+    fn protocol(&self) -> &'static str {
+        "vpcd"
+    }
+}
+
+impl Connection for Client {
+    fn reset(&mut self, reset_type: &ResetType) -> Result<(), EmvError> {
+        match reset_type {
+            ResetType::Warm => self.control(RESET),
+            ResetType::Cold => self.control(POWER_OFF).and_then(|_| self.control(POWER_ON)),
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<(), EmvError> {
+        self.control(POWER_ON)
+    }
+
+    fn transaction(&mut self, exchange: Exchange) -> Result<(), EmvError> {
+        // Nobody else shares the socket, so the card is already held exclusively
+        exchange(self);
+        Ok(())
+    }
+}
+
+/// Connects to a vicc waiting for a reader at the address and powers the card on
+pub fn connect(address: &str) -> Result<Client, EmvError> {
+    let stream = TcpStream::connect(address).map_err(|err| EmvError::transport("Failed to connect to remote card", err))?;
+    let client = Client { stream };
+    client.control(POWER_ON)?;
+    Ok(client)
+}
+
+/// Exposes the simulated card as a vicc, either connecting to the vpcd reader at the address or waiting for one to
+/// connect to it
+pub fn serve(address: &str, listen: bool, card: &mut SimulatedCard, report: &mut dyn FnMut(Event)) -> Result<(), EmvError> {
+    if listen {
+        let listener = TcpListener::bind(address).map_err(|err| EmvError::transport("Failed to listen for vpcd", err))?;
+        report(Event::Listening(address));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    report(Event::Connected(address));
+                    exchange(&stream, card, report)?;
+                    report(Event::Disconnected);
+                }
+                Err(err) => report(Event::Rejected(EmvError::transport("Failed to accept vpcd connection", err))),
+            }
+        }
+        Ok(())
+    } else {
+        let stream = TcpStream::connect(address).map_err(|err| EmvError::transport("Failed to connect to vpcd", err))?;
+        report(Event::Connected(address));
+        exchange(&stream, card, report)
+    }
+}
+
+/// Answers the reader's messages until it closes the connection
+fn exchange(stream: &TcpStream, card: &mut SimulatedCard, report: &mut dyn FnMut(Event)) -> Result<(), EmvError> {
+    loop {
+        let message = match receive(stream) {
+            Ok(message) => message,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(EmvError::transport("Failed to receive message from vpcd", err)),
+        };
+        let result = match message.as_slice() {
+            [POWER_OFF] | [POWER_ON] | [RESET] => {
+                card.reset();
+                Ok(())
+            }
+            [GET_ATR] => send(stream, &card.atr()),
+            command => {
+                let response = card.process(command);
+                report(Event::Exchanged(command, &response));
+                send(stream, &response)
+            }
+        };
+        result.map_err(|err| EmvError::transport("Failed to answer vpcd", err))?;
+    }
+}
+
+/// Every message is prefixed by its length as a big-endian u16, longer messages being rejected
+fn send(mut stream: &TcpStream, message: &[u8]) -> io::Result<()> {
+    let length = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("the {} byte message doesn't fit the u16 length", message.len())))?;
+    stream.write_all(&length.to_be_bytes())?;
+    stream.write_all(message)
+}
+
+fn receive(mut stream: &TcpStream) -> io::Result<Vec<u8>> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
@@ -1,64 +1,119 @@
-use structopt::StructOpt;
-
 use crate::apdu::capdu;
 use crate::apdu::capdu::{APDU, CryptogramType};
-use crate::apdu::rapdu::{RAPDU, Status};
-use crate::cli::interface::{Command, Emv, Mode};
-use crate::connection::usb;
-use crate::tlv::parser::TLV;
-
-fn send(card: &pcsc::Card, apdu: APDU) {
-    usb::transmit(card, &apdu)
-        .map(|response| {
-            match response {
-                RAPDU { status: Status::ResponseAvailable { length }, .. } => {
-                    read_response(card, length);
-                }
-                RAPDU { status: Status::WrongLengthLe { length }, .. } => {
-                    usb::transmit(card, &apdu.with_length(length));
-                }
-                _ => {
-                    response;
-                }
-            }
-        });
+use crate::apdu::rapdu::{Category, RAPDU, Status, StatusKind};
+use crate::atr::parser::ATR;
+use crate::error::EmvError;
+use crate::tlv::dol;
+use crate::tlv::parser::Tag;
+use crate::connection;
+use crate::connection::{Connection, ResetType, Transport};
+
+/// Counters read by `counters` before the issuer-proprietary ones: ATC, last online ATC and PIN Try Counter
+const COUNTERS: [u16; 3] = [0x9F36, 0x9F13, 0x9F17];
+
+/// Transmits the APDU, fetching the response the card holds back, and returns the final R-APDU when the command
+/// completed normally
+fn send(card: &dyn Transport, apdu: APDU) -> Result<RAPDU, EmvError> {
+    let response = match connection::transmit(card, &apdu)? {
+        RAPDU { status: Status { kind: StatusKind::ResponseAvailable { length }, .. }, .. } => {
+            return read_response(card, short_length(length));
+        }
+        RAPDU { status: Status { kind: StatusKind::WrongLengthLe { length }, .. }, .. } => {
+            connection::transmit(card, &apdu.with_le(short_length(length)).map_err(EmvError::Parse)?)?
+        }
+        response => response,
+    };
+    match response.status.category() {
+        Category::Normal => Ok(response),
+        _ => Err(EmvError::Status(response.status)),
+    }
 }
 
 /// Select the provided Application ID
-pub fn select_application(card: &pcsc::Card, aid: Vec<u8>) {
-    let apdu = capdu::select(aid);
+pub fn select_application(card: &dyn Transport, aid: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::select(aid).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
-/// Read value from a tag
-pub fn get_data(card: &pcsc::Card, tag: u16) {
-    let apdu = capdu::get_data(tag);
-    send(card, apdu)
+/// Read value from a tag, retrying with the other class when the card rejects the first one
+pub fn get_data(card: &dyn Transport, tag: u16) -> Result<Vec<u8>, EmvError> {
+    let response = match send(card, capdu::get_data(true, tag).map_err(EmvError::Parse)?) {
+        Err(EmvError::Status(Status { kind: StatusKind::ClassNotSupported, .. }))
+        | Err(EmvError::Status(Status { kind: StatusKind::InstructionCodeNotSupported, .. })) => {
+            send(card, capdu::get_data(false, tag).map_err(EmvError::Parse)?)?
+        }
+        response => response?,
+    };
+    response.find(&Tag::new(tag))
+        .map(|tlv| tlv.value().to_vec())
+        .ok_or(EmvError::Parse("The requested data object is missing from the response!"))
+}
+
+/// Reads the counters the card discloses, along with the issuer-proprietary ones requested
+pub fn counters(card: &dyn Transport, proprietary: &[u16]) -> Vec<(u16, Result<Vec<u8>, EmvError>)> {
+    COUNTERS.iter().chain(proprietary)
+        .map(|tag| (*tag, get_data(card, *tag)))
+        .collect()
 }
 
 /// **[SECURE]** Writes the provided value to a tag
-pub fn put_data(card: &pcsc::Card, tag: u16, value: Vec<u8>, mac: Vec<u8>) {
+pub fn put_data(card: &dyn Transport, tag: u16, value: Vec<u8>, mac: Vec<u8>) -> Result<RAPDU, EmvError> {
     let mut data = value.clone();
     data.extend(mac);
-    let apdu = capdu::put_data(true, tag, data);
+    let apdu = capdu::put_data(true, tag, data).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
 /// Read information from the Record structure
-pub fn read_record(card: &pcsc::Card, record: u8, sfi: u8) {
-    let apdu = capdu::read_record(record, sfi);
+pub fn read_record(card: &dyn Transport, record: u8, sfi: u8) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::read_record(record, sfi).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
-/// Check if the provided plaintext PIN is correct
-pub fn verify(card: &pcsc::Card, pin: Vec<u8>) {
-    let apdu = capdu::verify(pin);
+/// Reads the PIN Try Counter (9F17), which the card may not disclose
+pub fn pin_try_counter(card: &dyn Transport) -> Result<u8, EmvError> {
+    get_data(card, 0x9F17)?.first().copied().ok_or(EmvError::Parse("The PIN Try Counter is empty!"))
+}
+
+/// Records of the transaction log along with the Log Format describing them
+pub struct TransactionLog {
+    pub format: Vec<dol::DataObject>,
+    pub records: Vec<Vec<u8>>,
+}
+
+/// Reads the transaction log located by the Log Entry (9F4D), which is read with GET DATA when not provided
+pub fn transaction_log(card: &dyn Transport, log_entry: Option<Vec<u8>>) -> Result<TransactionLog, EmvError> {
+    let log_entry = match log_entry {
+        Some(log_entry) => log_entry,
+        None => get_data(card, 0x9F4D)?,
+    };
+    let (sfi, count) = match log_entry.as_slice() {
+        [sfi, count] => (*sfi, *count),
+        _ => return Err(EmvError::Parse("The Log Entry must hold the SFI and the number of records of the log!")),
+    };
+    let format = dol::parse(&get_data(card, 0x9F4F)?).map_err(EmvError::Parse)?;
+
+    let mut records = Vec::new();
+    for record in 1..=count {
+        // The log ends at the first missing record, the most recent transaction being the first
+        match send(card, capdu::read_record(record, sfi << 3 | 0x04).map_err(EmvError::Parse)?) {
+            Ok(response) => records.push(response.raw),
+            Err(EmvError::Status(Status { kind: StatusKind::RecordNotFound, .. })) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(TransactionLog { format, records })
+}
+
+/// Check if the provided PIN is correct, sent in plaintext or `enciphered` with a public key of the card
+pub fn verify(card: &dyn Transport, enciphered: bool, pin: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::verify(enciphered, pin).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
 /// Get transactional data information
-pub fn get_processing_options(card: &pcsc::Card) {
-    let apdu = capdu::get_processing_options();
+pub fn get_processing_options(card: &dyn Transport, pdol_data: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::get_processing_options(pdol_data).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
@@ -66,36 +121,104 @@ pub fn get_processing_options(card: &pcsc::Card) {
 ///
 /// - `ARQC`: Before transaction processing, used to authenticate transaction data, also known as First Generate AC
 /// - `TC|AAC`: After transaction processing, also known as Second Generate AC
-pub fn generate_ac(card: &pcsc::Card, cryptogram_type: CryptogramType, cdol: Vec<u8>) {
-    let apdu = capdu::generate_ac(cryptogram_type, cdol);
+///
+/// With `cda`, the card signs the cryptogram along with the transaction data, as in Combined Data Authentication.
+pub fn generate_ac(card: &dyn Transport, cryptogram_type: CryptogramType, cda: bool, cdol: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::generate_ac(cryptogram_type, cda, cdol).map_err(EmvError::Parse)?;
+    send(card, apdu)
+}
+
+/// Requests an unpredictable number from the card, to be used in a security related procedure
+pub fn get_challenge(card: &dyn Transport) -> Result<Vec<u8>, EmvError> {
+    send(card, capdu::get_challenge().map_err(EmvError::Parse)?).map(|response| response.raw)
+}
+
+/// Requests the Signed Dynamic Application Data over the DDOL data, as in Dynamic Data Authentication
+pub fn internal_authenticate(card: &dyn Transport, ddol: Vec<u8>) -> Result<Vec<u8>, EmvError> {
+    let response = send(card, capdu::internal_authenticate(ddol).map_err(EmvError::Parse)?)?;
+    // Format 1 responses carry the signed data as the whole value of template 80
+    response.find(&Tag::SignedDynamicApplicationData)
+        .or_else(|| response.find(&Tag::ResponseMessageTemplateFormat1))
+        .map(|tlv| tlv.value().to_vec())
+        .ok_or(EmvError::Parse("The Signed Dynamic Application Data is missing from the response!"))
+}
+
+/// Computes the CVC3 of the Track 1 and Track 2 data in mag-stripe mode, over the UDOL data
+pub fn compute_cryptographic_checksum(card: &dyn Transport, udol: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::compute_cryptographic_checksum(udol).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
 /// **[SECURE]** Resets the PIN Try Counter
-pub fn unblock_pin(card: &pcsc::Card, mac: Vec<u8>) {
-    let apdu = capdu::pin_unblock(mac);
+pub fn unblock_pin(card: &dyn Transport, mac: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::pin_unblock(mac).map_err(EmvError::Parse)?;
+    send(card, apdu)
+}
+
+/// Authenticates the issuer with the Issuer Authentication Data (91) received in the authorisation response
+pub fn external_authenticate(card: &dyn Transport, issuer_authentication_data: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::external_authenticate(issuer_authentication_data).map_err(EmvError::Parse)?;
+    send(card, apdu)
+}
+
+/// Changes the offline PIN without issuer script protection, as supported by some proprietary applications
+pub fn offline_change_pin(card: &dyn Transport, pin: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::offline_change_pin(pin).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
-pub fn change_pin(card: &pcsc::Card, pin: Vec<u8>, mac: Vec<u8>) {
-    let apdu = capdu::pin_change(pin, mac);
+pub fn change_pin(card: &dyn Transport, pin: Vec<u8>, mac: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::pin_change(pin, mac).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
 /// **[SECURE]** Blocks the selected application
-pub fn application_block(card: &pcsc::Card, mac: Vec<u8>) {
-    let apdu = capdu::application_block(mac);
+pub fn application_block(card: &dyn Transport, mac: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::application_block(mac).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
 
 /// **[SECURE]** Unblocks the selected application
-pub fn application_unblock(card: &pcsc::Card, mac: Vec<u8>) {
-    let apdu = capdu::application_unblock(mac);
+pub fn application_unblock(card: &dyn Transport, mac: Vec<u8>) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::application_unblock(mac).map_err(EmvError::Parse)?;
+    send(card, apdu)
+}
+
+/// Sends a raw C-APDU, handling its response like the named commands
+pub fn send_apdu(card: &dyn Transport, apdu: APDU) -> Result<RAPDU, EmvError> {
     send(card, apdu)
 }
 
+/// Parses the card's Answer To Reset
+pub fn answer_to_reset(card: &dyn Transport) -> Result<ATR, EmvError> {
+    let atr = card.atr()?;
+    ATR::parse(&atr).map_err(EmvError::Parse)
+}
+
+/// Resets the card and restarts the session, either keeping it powered (warm) or cycling its power (cold)
+pub fn reset(connection: &mut dyn Connection, reset_type: ResetType) -> Result<ATR, EmvError> {
+    connection.reset(&reset_type)?;
+    answer_to_reset(connection)
+}
+
+/// Re-establishes the connection with the card without resetting it
+pub fn reconnect(connection: &mut dyn Connection) -> Result<ATR, EmvError> {
+    connection.reconnect()?;
+    answer_to_reset(connection)
+}
+
+/// Holds the card exclusively while running the exchange, so other applications can't interleave APDUs
+pub fn transaction<F>(connection: &mut dyn Connection, exchange: F) -> Result<(), EmvError> where F: FnOnce(&dyn Transport) {
+    connection.transaction(Box::new(exchange))
+}
+
 /// Reads the response provided by the previous successful command
-fn read_response(card: &pcsc::Card, length: u8) {
-    let apdu = capdu::get_response(length);
+fn read_response(card: &dyn Transport, length: usize) -> Result<RAPDU, EmvError> {
+    let apdu = capdu::get_response(length).map_err(EmvError::Parse)?;
     send(card, apdu)
 }
+
+/// Lengths reported in SW2 are short Le values, where 00 stands for 256
+fn short_length(sw2: u8) -> usize {
+    if sw2 == 0 { capdu::SHORT_LE_MAX } else { usize::from(sw2) }
+}
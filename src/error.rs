@@ -0,0 +1,47 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::apdu::rapdu::Status;
+
+/// Failure of an operation with the card
+#[derive(Debug)]
+pub enum EmvError {
+    /// The card couldn't be reached or the exchange with it failed, as told by the underlying error
+    Transport { context: &'static str, source: Box<dyn Error + Send + Sync> },
+    /// The card processed the command but didn't complete it normally
+    Status(Status),
+    /// The card's answer couldn't be made sense of, or the command to send is malformed
+    Parse(&'static str),
+    /// The transaction can't go on with the card's data
+    Terminated(&'static str),
+    /// A command given to the tool is malformed
+    Command(String),
+}
+
+impl fmt::Display for EmvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmvError::Transport { context, source } => write!(f, "Transport error: {}: {}", context, source),
+            EmvError::Status(status) => write!(f, "Card answered {}", status),
+            EmvError::Parse(err) => write!(f, "Parse error: {}", err),
+            EmvError::Terminated(reason) => write!(f, "Transaction terminated: {}", reason),
+            EmvError::Command(err) => write!(f, "Invalid command: {}", err),
+        }
+    }
+}
+
+impl Error for EmvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmvError::Transport { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl EmvError {
+    /// Transport failure while doing what the context tells
+    pub fn transport<E>(context: &'static str, source: E) -> EmvError where E: Into<Box<dyn Error + Send + Sync>> {
+        EmvError::Transport { context, source: source.into() }
+    }
+}
@@ -0,0 +1,22 @@
+//! Building blocks of the `emv` tool, for exchanging APDUs with EMV smartcards from other applications and test
+//! harnesses: APDU builders and status words, BER-TLV and DOL parsing, ATR parsing, card transports, the
+//! operations of the controller and the transaction flow built on them.
+//!
+//! PC/SC readers are supported through the `pcsc` feature, enabled by default along with the command line tool
+//! (`cli`). Without default features the crate depends on `hex`, along with `num-bigint` and `sha1` for the RSA and
+//! SHA-1 of offline data authentication, cards being reached through the vpcd transport or any other implementation of
+//! [`connection::Transport`]. Terminal profiles are loaded from TOML files with the `profile` feature, also enabled by
+//! `cli`.
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod apdu;
+pub mod atr;
+pub mod connection;
+pub mod controller;
+pub mod error;
+pub mod session;
+pub mod simulator;
+pub mod terminal;
+pub mod tlv;
+pub mod transaction;
+pub mod utils;
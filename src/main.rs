@@ -1,86 +1,337 @@
 use std::fs::File;
-use std::io::{self, BufRead, Read, Write};
+use std::io::{self, BufRead};
 use std::path::PathBuf;
 use std::process;
 
+use emv::connection::{Connection, Transport, usb, vpcd};
+use emv::controller;
+use emv::error::EmvError;
+use emv::session::{EmvSession, Phase};
+use emv::simulator::card::SimulatedCard;
+use emv::terminal::TerminalProfile;
+use emv::transaction;
+use emv::transaction::Transaction;
 use structopt::StructOpt;
 
 use crate::cli::interface::{Command, Emv, Mode};
-use crate::connection::usb;
+use crate::cli::prompt::Prompt;
+use crate::cli::trace::Trace;
 
-mod apdu;
 mod cli;
-mod connection;
-mod tlv;
-mod utils;
-mod controller;
 
 fn main() {
     let args: Emv = Emv::from_args();
-    let card = usb::connect();
-    match card {
-        Some(card) => {
-            match args.mode {
-                Mode::Shell => { shell(card) }
-                Mode::Run { input } => run(input, card)
+    match &args.mode {
+        Mode::Shell => {
+            let session = session(&args);
+            shell(connect(&args).as_mut(), session)
+        }
+        Mode::Run { input } => {
+            let session = session(&args);
+            run(input, connect(&args).as_mut(), session)
+        }
+        Mode::Serve { address, listen } => {
+            if let Err(err) = vpcd::serve(address, *listen, &mut SimulatedCard::new(), &mut cli::output::served) {
+                eprintln!("{}", err);
+                process::exit(1);
             }
         }
-        None => {
-            eprintln!("No card detected!");
+    }
+}
+
+/// Connects to the remote card when one is provided, otherwise to the card in the first PC/SC reader
+fn connect(args: &Emv) -> Box<dyn Connection> {
+    let connection: Result<Box<dyn Connection>, EmvError> = match &args.remote {
+        Some(address) => vpcd::connect(address).map(|client| {
+            println!("Using remote card: {}", address);
+            Box::new(client) as Box<dyn Connection>
+        }),
+        None => usb::connect(args.settings()).map(|reader| {
+            println!("Using reader: {}", reader.name());
+            Box::new(reader) as Box<dyn Connection>
+        }),
+    };
+    match connection {
+        Ok(connection) => {
+            cli::output::answer_to_reset(connection.as_ref(), controller::answer_to_reset(connection.as_ref()));
+            connection
+        }
+        Err(err) => {
+            eprintln!("{}", err);
             process::exit(1);
         }
     }
 }
 
-fn shell(card: pcsc::Card) {
+/// Starts the session with the terminal profile provided, or the default one
+fn session(args: &Emv) -> EmvSession {
+    let profile = match &args.terminal {
+        Some(path) => TerminalProfile::load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        }),
+        None => TerminalProfile::default(),
+    };
+    EmvSession::with_profile(profile)
+}
+
+fn shell(connection: &mut dyn Connection, mut session: EmvSession) {
     cli::announcement();
     loop {
-        cli::read_command()
-            .map(|cmd| execute(cmd, &card));
+        match cli::read_command() {
+            Some(Ok(Command::Begin)) => block(connection, &mut session),
+            Some(Ok(cmd)) => execute(cmd, connection, &mut session),
+            Some(Err(err)) => eprintln!("{}", err),
+            None => break,
+        }
     }
 }
 
-fn run(input: PathBuf, card: pcsc::Card) {
+/// Runs shell commands until `END` while holding the card exclusively
+fn block(connection: &mut dyn Connection, session: &mut EmvSession) {
+    let result = controller::transaction(connection, |card| {
+        println!("Transaction started, the card is held until END");
+        loop {
+            match cli::read_command() {
+                Some(Ok(Command::End)) | None => break,
+                Some(Ok(cmd)) => exchange(cmd, card, session),
+                Some(Err(err)) => eprintln!("{}", err),
+            }
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("Transaction failed: {}", err);
+    }
+}
+
+fn run(input: &PathBuf, connection: &mut dyn Connection, mut session: EmvSession) {
     let file = File::open(input).expect("File not found!");
-    for line in io::BufReader::new(file).lines() {
-        if let Ok(cmd) = line {
-            match Command::from_str(cmd) {
-                Ok(command) => execute(command, &card),
-                Err(error) => eprintln!("Error parsing command {:?}", error),
+    let mut commands = io::BufReader::new(file).lines()
+        .map_while(Result::ok)
+        .filter_map(|cmd| {
+            Command::from_str(cmd)
+                .map_err(|err| eprintln!("{}", err))
+                .ok()
+        })
+        // The whole script already runs within transactions
+        .filter(|command| !matches!(command, Command::Begin | Command::End))
+        .peekable();
+
+    while let Some(command) = commands.next() {
+        if command.controls_connection() {
+            execute(command, connection, &mut session);
+        } else {
+            // Hold the card until the script ends or needs to reset the connection
+            let result = controller::transaction(connection, |card| {
+                exchange(command, card, &mut session);
+                while let Some(command) = commands.next_if(|command| !command.controls_connection()) {
+                    exchange(command, card, &mut session);
+                }
+            });
+            if let Err(err) = result {
+                eprintln!("Transaction failed: {}", err);
             }
         }
     }
 }
 
-fn execute(command: Command, card: &pcsc::Card) {
+/// Executes commands that control the connection itself, delegating everything else to `exchange`
+fn execute(command: Command, connection: &mut dyn Connection, session: &mut EmvSession) {
+    let atr = match command {
+        Command::Reset { reset_type } => {
+            session.reset();
+            controller::reset(connection, reset_type)
+        }
+        Command::Reconnect => controller::reconnect(connection),
+        command => return exchange(command, connection, session),
+    };
+    match atr {
+        Ok(atr) => cli::output::answer_to_reset(connection, Ok(atr)),
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+/// Exchanges the command's APDUs with the card, showing them along with the outcome
+fn exchange(command: Command, card: &dyn Transport, session: &mut EmvSession) {
+    if let Err(err) = dispatch(command, &Trace::new(card), session) {
+        eprintln!("{}", err);
+    }
+}
+
+/// Runs the command, which reads what it needs from the session and records the card's answers in it
+fn dispatch(command: Command, card: &dyn Transport, session: &mut EmvSession) -> Result<(), EmvError> {
     match command {
         Command::Select { application } => {
-            controller::select_application(card, application);
+            let response = controller::select_application(card, application.clone())?;
+            session.select(application, &response);
         }
         Command::GetProcessingOptions => {
-            controller::get_processing_options(card);
+            let pdol_data = session.pdol_data();
+            let response = controller::get_processing_options(card, pdol_data.clone())?;
+            session.record_processing_options(&pdol_data, &response);
         }
-        Command::GenerateAC { cryptogram_type, cdol } => {
-            let cdol_value = cdol.unwrap_or_else(|| { cli::read_hex_input("Input the CDOL value: ") });
-            controller::generate_ac(card, cryptogram_type, cdol_value);
+        Command::GenerateAC { cryptogram_type, cdol, cda } => {
+            // CDOL1 is requested until the first cryptogram is returned, CDOL2 afterwards
+            let first = session.phase() < Phase::CardActionAnalysis;
+            let cryptogram_type = match cryptogram_type {
+                Some(cryptogram_type) => cryptogram_type,
+                None if first => {
+                    let (cryptogram_type, notes) = transaction::action::analyse(session);
+                    notes.iter().for_each(|note| println!("{}", note));
+                    cryptogram_type
+                }
+                // The Authorisation Response Code tells whether the issuer approved the transaction
+                None => match transaction::online::second_cryptogram(session) {
+                    Some(cryptogram_type) => cryptogram_type,
+                    None => {
+                        eprintln!("The cryptogram type of the second GENERATE AC must be provided, or the Authorisation Response Code (8A) set");
+                        return Ok(());
+                    }
+                },
+            };
+            let cdol_value = match cdol.or_else(|| session.cdol_data(first)) {
+                Some(cdol_value) => cdol_value,
+                None => cli::read_hex_input("Input the CDOL value: ")?,
+            };
+            let response = controller::generate_ac(card, cryptogram_type, cda, cdol_value.clone())?;
+            session.record_cryptogram(&cdol_value, &response);
+            // The ICC public key is recovered from the records read so far to check the signature
+            if cda {
+                let (icc_key, mut notes) = transaction::oda::cda_key(session);
+                if let Some(icc_key) = icc_key {
+                    notes.extend(transaction::oda::cda(session, &icc_key, &response));
+                }
+                notes.iter().for_each(|note| println!("{}", note));
+            }
         }
         Command::PutData { tag, value } => {
-            controller::put_data(card, tag, value, cli::read_hex_input("Input the MAC: "));
+            controller::put_data(card, tag, value, cli::read_hex_input("Input the MAC: ")?)?;
         }
         Command::GetData { tag } => {
-            controller::get_data(card, tag);
+            let value = controller::get_data(card, tag)?;
+            cli::output::data_object(tag, &value);
+            session.store(tag, value);
+        }
+        Command::TransactionLog { log_entry } => {
+            // The Log Entry is usually found in the FCI
+            let log_entry = log_entry.or_else(|| session.card(0x9F4D).map(<[u8]>::to_vec));
+            cli::log::print(&controller::transaction_log(card, log_entry)?);
+        }
+        Command::Counters { proprietary } => {
+            for (tag, value) in controller::counters(card, &proprietary) {
+                match value {
+                    Ok(value) => {
+                        cli::output::data_object(tag, &value);
+                        session.store(tag, value);
+                    }
+                    Err(err) => eprintln!("The card doesn't disclose {:02X}: {}", tag, err),
+                }
+            }
         }
         Command::ReadRecord { record, sfi } => {
-            controller::read_record(&card, record, sfi);
+            let response = controller::read_record(card, record, sfi)?;
+            // The reference control parameter holds the SFI in its five high bits
+            session.record_read(sfi >> 3, record, &response);
+            session.advance(Phase::ReadApplicationData);
         }
         Command::Verify { pin } => {
-            controller::verify(&card, pin);
+            match controller::pin_try_counter(card) {
+                Ok(0) => eprintln!("Warning: the PIN is blocked, the card will reject it"),
+                Ok(1) => eprintln!("Warning: last PIN try, a wrong PIN blocks the card"),
+                _ => {}
+            }
+            controller::verify(card, false, pin)?;
+            session.advance(Phase::CardholderVerification);
+        }
+        Command::ExternalAuthenticate { issuer_authentication_data } => {
+            controller::external_authenticate(card, issuer_authentication_data.clone())?;
+            session.set_terminal(0x91, issuer_authentication_data);
+            session.advance(Phase::OnlineProcessing);
+        }
+        Command::OfflineChangePin { pin } => {
+            controller::offline_change_pin(card, pin)?;
+        }
+        Command::GetChallenge => {
+            let challenge = controller::get_challenge(card)?;
+            println!("Unpredictable number: {}", hex::encode_upper(challenge));
+        }
+        Command::InternalAuthenticate { ddol } => {
+            let ddol_value = match ddol.or_else(|| session.ddol_data()) {
+                Some(ddol_value) => ddol_value,
+                None => cli::read_hex_input("Input the DDOL value: ")?,
+            };
+            let signed_data = controller::internal_authenticate(card, ddol_value)?;
+            println!("Signed Dynamic Application Data ({} bytes): {}", signed_data.len(), hex::encode_upper(&signed_data));
+            session.store(0x9F4B, signed_data);
+            session.advance(Phase::OfflineDataAuthentication);
+        }
+        Command::ComputeCryptographicChecksum { udol } => {
+            let response = controller::compute_cryptographic_checksum(card, udol)?;
+            cli::output::cryptographic_checksum(&response);
+            session.record(&response);
+        }
+        Command::ApplicationBlock => {
+            controller::application_block(card, cli::read_hex_input("Input the MAC: ")?)?;
+        }
+        Command::ApplicationUnblock => {
+            controller::application_unblock(card, cli::read_hex_input("Input the MAC: ")?)?;
+        }
+        Command::PinStatus => {
+            let tries = controller::pin_try_counter(card)?;
+            session.store(0x9F17, vec![tries]);
+            cli::output::data_object(0x9F17, &[tries]);
+            if tries == 0 {
+                println!("PIN blocked");
+            }
         }
         Command::PinUnblock => {
-            controller::unblock_pin(&card, cli::read_hex_input("Input the MAC: "));
+            controller::unblock_pin(card, cli::read_hex_input("Input the MAC: ")?)?;
         }
         Command::PinChange { pin } => {
-            controller::change_pin(&card, pin, cli::read_hex_input("Input the MAC: "));
+            controller::change_pin(card, pin, cli::read_hex_input("Input the MAC: ")?)?;
+        }
+        Command::Send { apdu } => {
+            let response = controller::send_apdu(card, apdu)?;
+            session.record(&response);
+        }
+        Command::Transaction { aid, step } => {
+            let aid = aid.or_else(|| session.application().map(<[u8]>::to_vec))
+                .ok_or(EmvError::Terminated("No application is selected, provide its AID"))?;
+            transact(card, session, Transaction::new(aid), step)?;
+        }
+        Command::CheckRestrictions => {
+            cli::output::step(&transaction::check_restrictions(session), session);
+        }
+        Command::State => {
+            print!("{}", session);
+        }
+        Command::Set { tag, value } => {
+            session.set_terminal(tag, value);
+        }
+        Command::Atr => {
+            cli::output::answer_to_reset(card, controller::answer_to_reset(card));
+        }
+        Command::Reset { .. } | Command::Reconnect => {
+            eprintln!("The connection can't be reset while a transaction is in progress");
+        }
+        Command::Begin => {
+            eprintln!("A transaction is already in progress");
+        }
+        Command::End => {
+            eprintln!("There is no transaction in progress");
+        }
+    }
+    Ok(())
+}
+
+/// Runs the transaction phase by phase, waiting for the user before each one when stepping through it
+fn transact(card: &dyn Transport, session: &mut EmvSession, mut transaction: Transaction, step: bool) -> Result<(), EmvError> {
+    while let Some(phase) = transaction.next_phase() {
+        if step && !cli::proceed(format!("Next: {} (enter to continue, q to stop) ", phase)) {
+            println!("Transaction stopped before {}", phase);
+            return Ok(());
         }
+        cli::output::step(&transaction.step(card, session, &Prompt)?, session);
     }
+    Ok(())
 }
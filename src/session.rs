@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::apdu::rapdu::RAPDU;
+use crate::tlv::dol;
+use crate::terminal::TerminalProfile;
+use crate::tlv::parser::{Tag, TLV};
+use crate::transaction::tvr::{Tsi, Tvr};
+use crate::utils::system;
+
+/// Unpredictable Number (9F37) of 4 bytes
+const DEFAULT_DDOL: [u8; 3] = [0x9F, 0x37, 0x04];
+
+/// Steps of a transaction (EMV Book 3 §10), in the order they take place
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Phase {
+    Idle,
+    ApplicationSelection,
+    InitiateApplicationProcessing,
+    ReadApplicationData,
+    OfflineDataAuthentication,
+    ProcessingRestrictions,
+    CardholderVerification,
+    TerminalRiskManagement,
+    TerminalActionAnalysis,
+    CardActionAnalysis,
+    OnlineProcessing,
+    Completion,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Idle => "Idle",
+            Phase::ApplicationSelection => "Application Selection",
+            Phase::InitiateApplicationProcessing => "Initiate Application Processing",
+            Phase::ReadApplicationData => "Read Application Data",
+            Phase::OfflineDataAuthentication => "Offline Data Authentication",
+            Phase::ProcessingRestrictions => "Processing Restrictions",
+            Phase::CardholderVerification => "Cardholder Verification",
+            Phase::TerminalRiskManagement => "Terminal Risk Management",
+            Phase::TerminalActionAnalysis => "Terminal Action Analysis",
+            Phase::CardActionAnalysis => "Card Action Analysis",
+            Phase::OnlineProcessing => "Online Processing",
+            Phase::Completion => "Completion",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// State of the EMV application being exchanged with: the selected application, every data object the card returned,
+/// the terminal's own data objects and how far the transaction went
+pub struct EmvSession {
+    profile: TerminalProfile,
+    application: Option<Vec<u8>>,
+    card_data: BTreeMap<u16, Vec<u8>>,
+    terminal_data: BTreeMap<u16, Vec<u8>>,
+    phase: Phase,
+    /// Records the AFL flags for offline data authentication, by AFL entry and record number, none for those that
+    /// aren't coded as a record template
+    authenticated_records: BTreeMap<(usize, u8), Option<Vec<u8>>>,
+    /// PDOL and CDOL data sent so far, which the CDA signature covers
+    transaction_data: Vec<u8>,
+}
+
+impl Default for EmvSession {
+    fn default() -> EmvSession {
+        EmvSession::new()
+    }
+}
+
+impl EmvSession {
+    /// Starts with the default terminal profile
+    pub fn new() -> EmvSession {
+        EmvSession::with_profile(TerminalProfile::default())
+    }
+
+    /// Starts with the terminal data of the profile, transacting a purchase of zero
+    pub fn with_profile(profile: TerminalProfile) -> EmvSession {
+        let terminal_data = vec![
+            (0x5F2A, profile.currency_code.to_vec()),
+            (0x9C, vec![0x00]),
+            (0x9F02, vec![0x00; 6]),
+            (0x9F03, vec![0x00; 6]),
+            (0x9F1A, profile.country_code.to_vec()),
+            (0x9F33, profile.capabilities.to_vec()),
+            (0x9F35, profile.terminal_type.to_vec()),
+            (0x9F40, profile.additional_capabilities.to_vec()),
+        ];
+        EmvSession {
+            profile,
+            application: None,
+            card_data: BTreeMap::new(),
+            terminal_data: terminal_data.into_iter().collect(),
+            phase: Phase::Idle,
+            authenticated_records: BTreeMap::new(),
+            transaction_data: vec![],
+        }
+    }
+
+    pub fn profile(&self) -> &TerminalProfile {
+        &self.profile
+    }
+
+    pub fn application(&self) -> Option<&[u8]> {
+        self.application.as_deref()
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Value of a data object returned by the card
+    pub fn card(&self, tag: u16) -> Option<&[u8]> {
+        self.card_data.get(&tag).map(Vec::as_slice)
+    }
+
+    /// Value of a data object held by the terminal
+    pub fn terminal(&self, tag: u16) -> Option<&[u8]> {
+        self.terminal_data.get(&tag).map(Vec::as_slice)
+    }
+
+    pub fn set_terminal(&mut self, tag: u16, value: Vec<u8>) {
+        self.terminal_data.insert(tag, value);
+    }
+
+    pub fn set_tvr(&mut self, bit: Tvr) {
+        self.set_bit(0x95, bit.position());
+    }
+
+    pub fn set_tsi(&mut self, bit: Tsi) {
+        self.set_bit(0x9B, bit.position());
+    }
+
+    fn set_bit(&mut self, tag: u16, (byte, mask): (usize, u8)) {
+        let value = self.terminal_data.entry(tag).or_default();
+        if value.len() <= byte {
+            value.resize(byte + 1, 0x00);
+        }
+        value[byte] |= mask;
+    }
+
+    /// Moves the transaction forward, phases already past being left as they are
+    pub fn advance(&mut self, phase: Phase) {
+        if phase > self.phase {
+            self.phase = phase;
+        }
+    }
+
+    /// Forgets the application and the card data, as after a reset
+    pub fn reset(&mut self) {
+        self.application = None;
+        self.card_data.clear();
+        self.phase = Phase::Idle;
+        self.authenticated_records.clear();
+        self.transaction_data.clear();
+    }
+
+    /// Starts a new transaction with the selected application, stamping it with the current date and time, a new
+    /// unpredictable number and cleared TVR and TSI, and taking the terminal's settings for the application
+    pub fn select(&mut self, aid: Vec<u8>, response: &RAPDU) {
+        self.reset();
+        self.record(response);
+        // The DF name holds the full AID when it was partially selected
+        let aid = self.card(0x84).map_or(aid, <[u8]>::to_vec);
+        match self.profile.application(&aid) {
+            Some(application) => self.terminal_data.insert(0x9F09, application.application_version.to_vec()),
+            None => self.terminal_data.remove(&0x9F09),
+        };
+        self.set_terminal(0x9F1B, self.profile.floor_limit(&aid).to_be_bytes().to_vec());
+        self.application = Some(aid);
+        self.set_terminal(0x9A, system::date().to_vec());
+        self.set_terminal(0x9F21, system::time().to_vec());
+        self.set_terminal(0x9F37, system::random(4));
+        self.set_terminal(0x95, vec![0x00; 5]);
+        self.set_terminal(0x9B, vec![0x00; 2]);
+        self.phase = Phase::ApplicationSelection;
+    }
+
+    /// Keeps every primitive data object of the response
+    pub fn record(&mut self, response: &RAPDU) {
+        for tlv in &response.data {
+            self.card_data.insert(tlv.tag_number(), tlv.value().to_vec());
+        }
+    }
+
+    /// Keeps the data objects of the record, along with the record itself when the AFL flags it for offline data
+    /// authentication. The record template (70) is left out for the SFIs 1 to 10.
+    pub fn record_read(&mut self, sfi: u8, number: u8, response: &RAPDU) {
+        self.record(response);
+        let afl = self.card(0x94).unwrap_or_default();
+        let entry = afl.chunks_exact(4)
+            .position(|entry| entry[0] >> 3 == sfi && number >= entry[1] && u16::from(number) < u16::from(entry[1]) + u16::from(entry[3]));
+        if let Some(entry) = entry {
+            let data = if sfi <= 10 {
+                TLV::parse(response.raw.clone()).ok()
+                    .filter(|(tlv, remainder)| tlv.tag_number() == 0x70 && remainder.is_empty())
+                    .map(|(tlv, _)| tlv.value().to_vec())
+            } else {
+                Some(response.raw.clone())
+            };
+            self.authenticated_records.insert((entry, number), data);
+        }
+    }
+
+    /// Concatenation of the records read for offline data authentication in the order of the AFL, none when one of them
+    /// can't take part in it
+    pub fn authenticated_records(&self) -> Option<Vec<u8>> {
+        self.authenticated_records.values().try_fold(vec![], |records, record| Some([records, record.clone()?].concat()))
+    }
+
+    /// PDOL and CDOL data sent so far, which the CDA signature covers
+    pub fn transaction_data(&self) -> &[u8] {
+        &self.transaction_data
+    }
+
+    pub fn store(&mut self, tag: u16, value: Vec<u8>) {
+        self.card_data.insert(tag, value);
+    }
+
+    /// Keeps the AIP and AFL, which format 1 responses concatenate in template 80, and the PDOL data sent
+    pub fn record_processing_options(&mut self, pdol_data: &[u8], response: &RAPDU) {
+        self.transaction_data = pdol_data.to_vec();
+        self.record(response);
+        if let Some(value) = response.find(&Tag::ResponseMessageTemplateFormat1).map(|tlv| tlv.value()) {
+            if value.len() >= 2 {
+                self.store(0x82, value[..2].to_vec());
+                self.store(0x94, value[2..].to_vec());
+            }
+        }
+        self.advance(Phase::InitiateApplicationProcessing);
+    }
+
+    /// Keeps the CID, ATC, cryptogram and IAD, which format 1 responses concatenate in template 80, and the CDOL data
+    /// sent, telling the first GENERATE AC from the second
+    pub fn record_cryptogram(&mut self, cdol_data: &[u8], response: &RAPDU) {
+        self.transaction_data.extend(cdol_data);
+        self.record(response);
+        if let Some(value) = response.find(&Tag::ResponseMessageTemplateFormat1).map(|tlv| tlv.value()) {
+            if value.len() >= 11 {
+                self.store(0x9F27, value[..1].to_vec());
+                self.store(0x9F36, value[1..3].to_vec());
+                self.store(0x9F26, value[3..11].to_vec());
+                self.store(0x9F10, value[11..].to_vec());
+            }
+        }
+        if self.phase < Phase::CardActionAnalysis {
+            self.advance(Phase::CardActionAnalysis);
+        } else {
+            self.advance(Phase::Completion);
+        }
+    }
+
+    /// Data requested by the PDOL (9F38), empty when the card has none
+    pub fn pdol_data(&self) -> Vec<u8> {
+        self.dol_data(0x9F38).unwrap_or_default()
+    }
+
+    /// Data requested by CDOL1 (8C) or CDOL2 (8D), when the card provided it
+    pub fn cdol_data(&self, first: bool) -> Option<Vec<u8>> {
+        self.dol_data(if first { 0x8C } else { 0x8D })
+    }
+
+    /// Data requested by the DDOL (9F49), the default DDOL asking for the Unpredictable Number (9F37) when the card
+    /// has none
+    pub fn ddol_data(&self) -> Option<Vec<u8>> {
+        self.build(self.card(0x9F49).unwrap_or(&DEFAULT_DDOL))
+    }
+
+    /// Builds the data requested by one of the card's DOLs, terminal data taking precedence over the card's
+    pub fn dol_data(&self, tag: u16) -> Option<Vec<u8>> {
+        self.build(self.card(tag)?)
+    }
+
+    fn build(&self, dol: &[u8]) -> Option<Vec<u8>> {
+        let dol = dol::parse(dol).ok()?;
+        Some(dol::build(&dol, |tag| self.terminal(tag).or_else(|| self.card(tag))))
+    }
+}
+
+impl fmt::Display for EmvSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let application = self.application.as_ref().map_or(String::from("None"), hex::encode_upper);
+        writeln!(f, "Application: {}", application)?;
+        writeln!(f, "Phase: {}", self.phase)?;
+        for (title, data) in [("Card data", &self.card_data), ("Terminal data", &self.terminal_data)] {
+            writeln!(f, "{}: [", title)?;
+            for (tag, value) in data {
+                writeln!(f, "   > {:02X} {} = 0x{}", tag, Tag::new(*tag), hex::encode_upper(value))?;
+            }
+            writeln!(f, "]")?;
+        }
+        Ok(())
+    }
+}
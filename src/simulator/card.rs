@@ -0,0 +1,385 @@
+use crate::tlv::dol;
+use crate::tlv::parser::TLV;
+use crate::utils::crypto;
+use crate::utils::extension::Extendable;
+
+/// T=0 card with "emv" as historical bytes
+const ATR: [u8; 5] = [0x3B, 0x03, 0x65, 0x6D, 0x76];
+const AID: [u8; 7] = [0xA0, 0x00, 0x00, 0x00, 0x04, 0x10, 0x10];
+const PIN: [u8; 2] = [0x12, 0x34];
+const PIN_TRY_LIMIT: u8 = 3;
+/// SFI and maximum number of records of the transaction log
+const LOG_ENTRY: [u8; 2] = [0x0B, 0x0A];
+
+const CDOL1: &str = "9F02069F03069F1A0295055F2A029A039C019F37049F35019F45029F4C089F34039F21039F7C14";
+const CDOL2: &str = "910A8A0295059F37049F4C08";
+/// Transaction date and time, amount, currency, country, ATC and CID of each transaction log record
+const LOG_FORMAT: &str = "9A039F21039F02065F2A029F1A029F36029F2701";
+/// Test certification authority key FA of the RID A000000004, whose public key is in `examples/terminal.toml`,
+/// certifying the issuer public key, which signed the static data: record 1 and the AIP. The issuer certifies the ICC
+/// public key in turn.
+const CA_PUBLIC_KEY_INDEX: u8 = 0xFA;
+const ISSUER_PUBLIC_KEY_CERTIFICATE: &str = "4204AB4E8D3E3F348D03F65D8623A1FDA5F577E168C988D2DD2E68F004C996E4E382D66FA1F5F6C99AB4E824006C0BFED187CFF67879BCCD3E3DFC4512EF5BB7EBFF018571A4740807ED34283E976ACD98F9DEFF53BE20A7860AEF68FF10BCB797CAFC29DA6F79CE9F89DE78AE3A0570E29186ABF2556F972F57C3DFBAC2B9E8";
+const ISSUER_PUBLIC_KEY_REMAINDER: &str = "17467746A828F4AA600355F6F98F78FFA4F02AAD";
+const ISSUER_PUBLIC_KEY_EXPONENT: u8 = 0x03;
+const SIGNED_STATIC_APPLICATION_DATA: &str = "049EF8C1994CC938A3ED354DDEB71962D8446D940FCC38C4D3D316048E33041A81FBBFDB9F7E3F3A79CA64508594C28C9DE0F8CFDC4B3C05E9848F154B9E569B2F734E0CB762E607CA1D9B58E9471A6C960F402487F92A514AF4026F862F8E913B61DD70E207B223FE5E7365B7132406";
+const ICC_PUBLIC_KEY_CERTIFICATE: &str = "AB4189F96786325F7DA974B83DE8EDB7F54E8E21441CC0087761D69EB95F82AEF17DE3AC508B09874E9755CF741A4FBDBE949E4A4D2106232DEE0EC9BE0BB4F4DA49FEB1C900315C1DD36059EE2BBF6223854B3EBA89E9EC9287B0130AB1251E30429A03E6A69D44AB1E6D1B7310ED89";
+const ICC_PUBLIC_KEY_REMAINDER: &str = "28922FC435FB45F58C5B341D4EFD54ECE3A60990885F0F6F092B";
+const ICC_PUBLIC_KEY_EXPONENT: u8 = 0x03;
+const ICC_MODULUS: &str = "CA9F38211847B41E01E125C2D50151342483E656040C7660AD8A06DB3AC7D55B21951AEDC98F8AE2A67BB40D4E77B04421FCDE6206BEAD6582BE5F95086E951D3363CE2EF37528922FC435FB45F58C5B341D4EFD54ECE3A60990885F0F6F092B";
+const ICC_PRIVATE_EXPONENT: &str = "8714D016102FCD695696192C8E00E0CD6DAD443958084EEB1E5C0492272FE39216636749310A5C9719A7CD5E344FCAD6E506D95D42B3DE3F3D29DBCAC0B857E272689ACCBB353BC6EABF9461E7E00C385F5E2196CAFBAE15F0FB114D89CE001B";
+/// Offline enciphered PIN, then offline plaintext PIN, then online PIN, then signature, falling back to no CVM
+const CVM_LIST: &str = "00000000000000004403410342035E031F00";
+
+/// Minimal EMV application answering the commands `emv` issues, meant for exercising the tool and PC/SC
+/// applications without a physical card. Its application cryptograms are not cryptographically meaningful, while
+/// offline data authentication works with test keys.
+pub struct SimulatedCard {
+    selected: bool,
+    pin_try_counter: u8,
+    application_transaction_counter: u16,
+    last_online_application_transaction_counter: u16,
+    requested_arqc: bool,
+    issuer_authenticated: bool,
+    blocked: bool,
+    challenges: u32,
+    /// CDOL1 data of the transaction in progress
+    transaction_data: Vec<u8>,
+    /// Most recent transaction first
+    log: Vec<Vec<u8>>,
+}
+
+impl Default for SimulatedCard {
+    fn default() -> SimulatedCard {
+        SimulatedCard::new()
+    }
+}
+
+impl SimulatedCard {
+    pub fn new() -> SimulatedCard {
+        SimulatedCard {
+            selected: false,
+            pin_try_counter: PIN_TRY_LIMIT,
+            application_transaction_counter: 0,
+            last_online_application_transaction_counter: 0,
+            requested_arqc: false,
+            issuer_authenticated: false,
+            blocked: false,
+            challenges: 0,
+            transaction_data: vec![],
+            log: vec![],
+        }
+    }
+
+    pub fn atr(&self) -> Vec<u8> {
+        ATR.to_vec()
+    }
+
+    /// Resets the volatile state on power cycles and resets, counters are kept as they would be in the card's
+    /// persistent memory
+    pub fn reset(&mut self) {
+        self.selected = false;
+        self.requested_arqc = false;
+        self.issuer_authenticated = false;
+    }
+
+    /// Processes a C-APDU, returning the R-APDU with trailing status words
+    pub fn process(&mut self, command: &[u8]) -> Vec<u8> {
+        if command.len() < 4 {
+            return vec![0x67, 0x00];
+        }
+        let (cla, ins, p1, p2) = (command[0], command[1], command[2], command[3]);
+        let data = if command.len() > 5 { &command[5..5 + usize::from(command[4]).min(command.len() - 5)] } else { &[] };
+
+        let result = match (cla, ins) {
+            (0x00, 0xA4) => self.select(p1, data),
+            (0x80, 0xA8) => self.get_processing_options(),
+            (0x00, 0xB2) => self.read_record(p1, p2 >> 3),
+            (0x80, 0xCA) | (0x00, 0xCA) => self.get_data(p1.extend(p2)),
+            (0x00, 0x20) => self.verify(p2, data),
+            (0x80, 0xAE) => self.generate_ac(p1, data),
+            (0x00, 0x82) => self.external_authenticate(data),
+            (0x00, 0x84) => self.get_challenge(),
+            (0x00, 0x88) => self.internal_authenticate(data),
+            (0x80, 0x2A) => self.compute_cryptographic_checksum(p1.extend(p2), data),
+            // Issuer script commands, whose MAC isn't checked
+            (0x84, 0x1E) => self.application_block(true),
+            (0x84, 0x18) => self.application_block(false),
+            (_, 0xA4) | (_, 0xA8) | (_, 0xB2) | (_, 0xCA) | (_, 0x20) | (_, 0xAE) | (_, 0x82) | (_, 0x84) | (_, 0x88) | (_, 0x2A)
+            | (_, 0x1E) | (_, 0x18) => {
+                Err(0x6E00)
+            }
+            _ => Err(0x6D00),
+        };
+
+        match result {
+            Ok(mut response) => {
+                response.extend(&[0x90, 0x00]);
+                response
+            }
+            Err(sw) => sw.to_be_bytes().to_vec(),
+        }
+    }
+
+    fn select(&mut self, p1: u8, aid: &[u8]) -> Result<Vec<u8>, u16> {
+        // Partial selection by the first bytes of the AID is allowed
+        if p1 != 0x04 || aid.is_empty() || !AID.starts_with(aid) {
+            self.selected = false;
+            return Err(0x6A82);
+        }
+        self.selected = true;
+        let mut proprietary = TLV::encode(0x50, b"MASTERCARD");
+        proprietary.extend(TLV::encode(0x87, &[0x01]));
+        proprietary.extend(TLV::encode(0xBF0C, &TLV::encode(0x9F4D, &LOG_ENTRY)));
+        let mut fci = TLV::encode(0x84, &AID);
+        fci.extend(TLV::encode(0xA5, &proprietary));
+        if self.blocked {
+            // Blocked applications are still selected, but only with a warning
+            return Err(0x6283);
+        }
+        Ok(TLV::encode(0x6F, &fci))
+    }
+
+    fn get_processing_options(&mut self) -> Result<Vec<u8>, u16> {
+        self.require_selection()?;
+        self.requested_arqc = false;
+        // SDA, DDA, cardholder verification, terminal risk management, issuer authentication and CDA supported, with
+        // the first record taking part in offline data authentication
+        let mut data = TLV::encode(0x82, &[0x7D, 0x00]);
+        data.extend(TLV::encode(0x94, &[0x08, 0x01, 0x05, 0x01]));
+        Ok(TLV::encode(0x77, &data))
+    }
+
+    fn read_record(&self, record: u8, sfi: u8) -> Result<Vec<u8>, u16> {
+        self.require_selection()?;
+        let record = match (sfi, record) {
+            (1, 1) => [
+                TLV::encode(0x5A, &[0x54, 0x13, 0x33, 0x00, 0x89, 0x01, 0x00, 0x12]),
+                TLV::encode(0x5F24, &[0x27, 0x12, 0x31]),
+                TLV::encode(0x5F25, &[0x20, 0x01, 0x01]),
+                TLV::encode(0x5F28, &[0x00, 0x76]),
+                TLV::encode(0x5F34, &[0x01]),
+                TLV::encode(0x9F07, &[0xFF, 0x00]),
+                TLV::encode(0x8C, &hex::decode(CDOL1).unwrap()),
+                TLV::encode(0x8D, &hex::decode(CDOL2).unwrap()),
+                TLV::encode(0x8E, &hex::decode(CVM_LIST).unwrap()),
+            ].concat(),
+            (1, 2) => [
+                TLV::encode(0x9F08, &[0x00, 0x02]),
+                TLV::encode(0x9F0D, &[0xF4, 0x70, 0xC4, 0x98, 0x00]),
+                TLV::encode(0x9F0E, &[0x00, 0x00, 0x00, 0x00, 0x00]),
+                TLV::encode(0x9F0F, &[0xF4, 0x70, 0xC4, 0x98, 0x00]),
+                TLV::encode(0x9F42, &[0x09, 0x86]),
+                TLV::encode(0x9F14, &[0x05]),
+                TLV::encode(0x9F23, &[0x0A]),
+            ].concat(),
+            (1, 3) => [
+                TLV::encode(0x8F, &[CA_PUBLIC_KEY_INDEX]),
+                TLV::encode(0x90, &hex::decode(ISSUER_PUBLIC_KEY_CERTIFICATE).unwrap()),
+                TLV::encode(0x92, &hex::decode(ISSUER_PUBLIC_KEY_REMAINDER).unwrap()),
+                TLV::encode(0x9F32, &[ISSUER_PUBLIC_KEY_EXPONENT]),
+            ].concat(),
+            (1, 4) => [
+                TLV::encode(0x93, &hex::decode(SIGNED_STATIC_APPLICATION_DATA).unwrap()),
+                TLV::encode(0x9F4A, &[0x82]),
+            ].concat(),
+            (1, 5) => [
+                TLV::encode(0x9F46, &hex::decode(ICC_PUBLIC_KEY_CERTIFICATE).unwrap()),
+                TLV::encode(0x9F47, &[ICC_PUBLIC_KEY_EXPONENT]),
+                TLV::encode(0x9F48, &hex::decode(ICC_PUBLIC_KEY_REMAINDER).unwrap()),
+            ].concat(),
+            // Log records hold the bare values described by the Log Format
+            (sfi, record) if sfi == LOG_ENTRY[0] && record > 0 => {
+                return self.log.get(usize::from(record) - 1).cloned().ok_or(0x6A83);
+            }
+            _ => return Err(0x6A83),
+        };
+        Ok(TLV::encode(0x70, &record))
+    }
+
+    fn get_data(&self, tag: u16) -> Result<Vec<u8>, u16> {
+        let value = match tag {
+            0x9F36 => self.application_transaction_counter.to_be_bytes().to_vec(),
+            0x9F13 => self.last_online_application_transaction_counter.to_be_bytes().to_vec(),
+            0x9F17 => vec![self.pin_try_counter],
+            0x9F4D => LOG_ENTRY.to_vec(),
+            0x9F4F => hex::decode(LOG_FORMAT).unwrap(),
+            _ => return Err(0x6A88),
+        };
+        Ok(TLV::encode(tag, &value))
+    }
+
+    fn verify(&mut self, p2: u8, data: &[u8]) -> Result<Vec<u8>, u16> {
+        // The enciphered PIN data holds 7F, the PIN block and the latest challenge, deciphered with the ICC private key
+        let data = match p2 {
+            0x80 => data.to_vec(),
+            0x88 => {
+                let modulus = hex::decode(ICC_MODULUS).unwrap();
+                if data.len() != modulus.len() {
+                    return Err(0x6700);
+                }
+                let deciphered = crypto::rsa(data, &modulus, &hex::decode(ICC_PRIVATE_EXPONENT).unwrap());
+                let challenge = cryptogram(0x84, &self.challenges.to_be_bytes(), &[]);
+                if deciphered[0] != 0x7F || deciphered[9..17] != challenge {
+                    return Err(0x6984);
+                }
+                deciphered[1..9].to_vec()
+            }
+            _ => return Err(0x6A86),
+        };
+        if self.pin_try_counter == 0 {
+            return Err(0x6983);
+        }
+        if data.len() < 1 + PIN.len() || !data[1..].starts_with(&PIN) || data[0] & 0x0F != (PIN.len() * 2) as u8 {
+            self.pin_try_counter -= 1;
+            return Err(0x63C0 | u16::from(self.pin_try_counter));
+        }
+        self.pin_try_counter = PIN_TRY_LIMIT;
+        Ok(vec![])
+    }
+
+    fn generate_ac(&mut self, reference_control: u8, data: &[u8]) -> Result<Vec<u8>, u16> {
+        self.require_selection()?;
+        let second = self.requested_arqc;
+        if !second {
+            self.application_transaction_counter = self.application_transaction_counter.wrapping_add(1);
+            self.transaction_data = data.to_vec();
+        }
+
+        // Honour the requested cryptogram type, a second GENERATE AC can't request another ARQC and blocked applications
+        // always decline
+        let cid = match reference_control & 0xC0 {
+            _ if self.blocked => 0x00,
+            0x80 if !second => 0x80,
+            0x40 => 0x40,
+            _ => 0x00,
+        };
+        self.requested_arqc = cid == 0x80;
+        if second && cid == 0x40 {
+            self.last_online_application_transaction_counter = self.application_transaction_counter;
+        }
+
+        let atc = self.application_transaction_counter.to_be_bytes();
+        if cid != 0x80 {
+            self.log_transaction(cid, &atc);
+        }
+        let application_cryptogram = cryptogram(cid, &atc, data);
+        let issuer_application_data = TLV::encode(0x9F10, &[0x01, 0x10, 0xA0, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let mut response = TLV::encode(0x9F27, &[cid]);
+        response.extend(TLV::encode(0x9F36, &atc));
+        // AACs are never signed
+        if reference_control & 0x10 != 0 && cid != 0x00 {
+            // The Transaction Data Hash Code covers the CDOL1 data, then the CDOL2 data, and the response without the
+            // Signed Dynamic Application Data. There's no PDOL data.
+            let cdol_data = if second { [&self.transaction_data[..], data].concat() } else { data.to_vec() };
+            let transaction_data_hash = crypto::sha1(&[&cdol_data[..], &response, &issuer_application_data].concat());
+            let dynamic_number = cryptogram(0x88, &atc, data);
+            let dynamic_data = [&[dynamic_number.len() as u8][..], &dynamic_number, &[cid], &application_cryptogram, &transaction_data_hash].concat();
+            let unpredictable_number = unpredictable_number(if second { CDOL2 } else { CDOL1 }, data);
+            response.extend(TLV::encode(0x9F4B, &sign_dynamic_data(&dynamic_data, &unpredictable_number)));
+        } else {
+            response.extend(TLV::encode(0x9F26, &application_cryptogram));
+        }
+        response.extend(issuer_application_data);
+        Ok(TLV::encode(0x77, &response))
+    }
+
+    /// Logs the completed transaction from its CDOL1 data, which is left out when it doesn't follow the CDOL1
+    fn log_transaction(&mut self, cid: u8, atc: &[u8]) {
+        let cdol1 = dol::parse(&hex::decode(CDOL1).unwrap()).unwrap();
+        let values = match dol::split(&cdol1, &self.transaction_data) {
+            Ok(values) => values,
+            Err(_) => return,
+        };
+        let log_format = dol::parse(&hex::decode(LOG_FORMAT).unwrap()).unwrap();
+        let record = log_format.iter()
+            .flat_map(|entry| match entry.tag {
+                0x9F36 => atc.to_vec(),
+                0x9F27 => vec![cid],
+                tag => values.iter().find(|(value, _)| value.tag == tag).map_or(vec![0; entry.length], |(_, value)| value.to_vec()),
+            })
+            .collect();
+        self.log.insert(0, record);
+        self.log.truncate(usize::from(LOG_ENTRY[1]));
+    }
+
+    fn external_authenticate(&mut self, data: &[u8]) -> Result<Vec<u8>, u16> {
+        self.require_selection()?;
+        // Only once per transaction, between both GENERATE AC
+        if !self.requested_arqc || self.issuer_authenticated {
+            return Err(0x6985);
+        }
+        if !(8..=16).contains(&data.len()) {
+            return Err(0x6700);
+        }
+        self.issuer_authenticated = true;
+        Ok(vec![])
+    }
+
+    fn get_challenge(&mut self) -> Result<Vec<u8>, u16> {
+        self.challenges = self.challenges.wrapping_add(1);
+        Ok(cryptogram(0x84, &self.challenges.to_be_bytes(), &[]).to_vec())
+    }
+
+    /// Answers in format 1 with the Signed Dynamic Application Data over the DDOL data, the ICC Dynamic Number being
+    /// derived from it
+    fn internal_authenticate(&self, data: &[u8]) -> Result<Vec<u8>, u16> {
+        self.require_selection()?;
+        if data.is_empty() {
+            return Err(0x6700);
+        }
+        let dynamic_number = cryptogram(0x88, &self.application_transaction_counter.to_be_bytes(), data);
+        let dynamic_data = [&[dynamic_number.len() as u8][..], &dynamic_number].concat();
+        Ok(TLV::encode(0x80, &sign_dynamic_data(&dynamic_data, data)))
+    }
+
+    fn compute_cryptographic_checksum(&mut self, parameters: u16, data: &[u8]) -> Result<Vec<u8>, u16> {
+        self.require_selection()?;
+        if parameters != 0x8E80 {
+            return Err(0x6A86);
+        }
+        self.application_transaction_counter = self.application_transaction_counter.wrapping_add(1);
+        let atc = self.application_transaction_counter.to_be_bytes();
+        let checksum = cryptogram(0x2A, &atc, data);
+        let mut response = TLV::encode(0x9F61, &checksum[..2]);
+        response.extend(TLV::encode(0x9F60, &checksum[2..4]));
+        response.extend(TLV::encode(0x9F36, &atc));
+        Ok(TLV::encode(0x77, &response))
+    }
+
+    fn application_block(&mut self, blocked: bool) -> Result<Vec<u8>, u16> {
+        self.require_selection()?;
+        self.blocked = blocked;
+        Ok(vec![])
+    }
+
+    fn require_selection(&self) -> Result<(), u16> {
+        if self.selected { Ok(()) } else { Err(0x6985) }
+    }
+}
+
+/// Signs the ICC dynamic data, led by the ICC Dynamic Number, with the ICC private key (EMV Book 2 §6.5.2 and §6.6.1)
+fn sign_dynamic_data(dynamic_data: &[u8], terminal_data: &[u8]) -> Vec<u8> {
+    let modulus = hex::decode(ICC_MODULUS).unwrap();
+    let mut data = vec![0x05, 0x01, dynamic_data.len() as u8];
+    data.extend(dynamic_data);
+    data.resize(modulus.len() - 22, 0xBB);
+    let hash = crypto::sha1(&[&data[..], terminal_data].concat());
+    let signed = [&[0x6A], &data[..], &hash, &[0xBC]].concat();
+    crypto::rsa(&signed, &modulus, &hex::decode(ICC_PRIVATE_EXPONENT).unwrap())
+}
+
+/// Unpredictable Number (9F37) the CDOL data holds, which the terminal expects the CDA signature to cover
+fn unpredictable_number(cdol: &str, data: &[u8]) -> Vec<u8> {
+    let cdol = dol::parse(&hex::decode(cdol).unwrap()).unwrap();
+    dol::split(&cdol, data).ok()
+        .and_then(|values| values.iter().find(|(entry, _)| entry.tag == 0x9F37).map(|(_, value)| value.to_vec()))
+        .unwrap_or_default()
+}
+
+/// Deterministic stand-in for an application cryptogram, folding the inputs with FNV-1a
+fn cryptogram(cid: u8, atc: &[u8], data: &[u8]) -> [u8; 8] {
+    let hash = [cid].iter().chain(atc).chain(data)
+        .fold(0xCBF2_9CE4_8422_2325_u64, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01B3));
+    hash.to_be_bytes()
+}
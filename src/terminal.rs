@@ -0,0 +1,124 @@
+#[cfg(feature = "profile")]
+use std::fs;
+#[cfg(feature = "profile")]
+use std::path::Path;
+
+#[cfg(feature = "profile")]
+use serde::Deserialize;
+
+/// Terminal being emulated, the source of the terminal data objects sent to the card and of the limits and action codes
+/// the transaction decisions are based on. Profiles are written in TOML with values in hex, as in
+/// `examples/terminal.toml`, fields left out taking the default values.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "profile", derive(Deserialize))]
+#[cfg_attr(feature = "profile", serde(default, deny_unknown_fields))]
+pub struct TerminalProfile {
+    /// Terminal Type (9F35)
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub terminal_type: [u8; 1],
+    /// Terminal Capabilities (9F33)
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub capabilities: [u8; 3],
+    /// Additional Terminal Capabilities (9F40)
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub additional_capabilities: [u8; 5],
+    /// Terminal Country Code (9F1A)
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub country_code: [u8; 2],
+    /// Transaction Currency Code (5F2A)
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub currency_code: [u8; 2],
+    /// Terminal Floor Limit (9F1B) in the minor unit of the currency, unless the application has its own
+    pub floor_limit: u32,
+    pub random_selection: RandomSelection,
+    pub applications: Vec<ApplicationProfile>,
+    /// Certification authority public keys trusted for offline data authentication
+    pub ca_keys: Vec<CertificationAuthorityKey>,
+}
+
+/// Parameters of the random transaction selection (EMV Book 3 §10.6.2), amounts being in the minor unit of the
+/// currency
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "profile", derive(Deserialize))]
+#[cfg_attr(feature = "profile", serde(default, deny_unknown_fields))]
+pub struct RandomSelection {
+    pub threshold: u64,
+    pub target_percentage: u8,
+    pub max_target_percentage: u8,
+}
+
+/// Settings of the terminal for an application, which apply to every AID it prefixes
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "profile", derive(Deserialize))]
+#[cfg_attr(feature = "profile", serde(deny_unknown_fields))]
+pub struct ApplicationProfile {
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub aid: Vec<u8>,
+    /// Application Version Number (9F09) the terminal implements
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub application_version: [u8; 2],
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub tac_default: [u8; 5],
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub tac_denial: [u8; 5],
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub tac_online: [u8; 5],
+    pub floor_limit: Option<u32>,
+}
+
+/// Certification authority public key, told apart by the RID of the payment system and its index (8F)
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "profile", derive(Deserialize))]
+#[cfg_attr(feature = "profile", serde(deny_unknown_fields))]
+pub struct CertificationAuthorityKey {
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub rid: [u8; 5],
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub index: [u8; 1],
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub modulus: Vec<u8>,
+    #[cfg_attr(feature = "profile", serde(with = "hex"))]
+    pub exponent: Vec<u8>,
+}
+
+/// Attended online terminal in Brazil, with no floor limit nor random selection
+impl Default for TerminalProfile {
+    fn default() -> TerminalProfile {
+        TerminalProfile {
+            terminal_type: [0x22],
+            capabilities: [0xE0, 0xF8, 0xC8],
+            additional_capabilities: [0x60, 0x00, 0xF0, 0xA0, 0x01],
+            country_code: [0x00, 0x76],
+            currency_code: [0x09, 0x86],
+            floor_limit: 0,
+            random_selection: RandomSelection::default(),
+            applications: vec![],
+            ca_keys: vec![],
+        }
+    }
+}
+
+impl TerminalProfile {
+    /// Loads a profile from a TOML file
+    #[cfg(feature = "profile")]
+    pub fn load(path: &Path) -> Result<TerminalProfile, String> {
+        let profile = fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        toml::from_str(&profile).map_err(|err| format!("Invalid terminal profile {}: {}", path.display(), err))
+    }
+
+    /// Settings for the application, the one with the longest matching AID prevailing
+    pub fn application(&self, aid: &[u8]) -> Option<&ApplicationProfile> {
+        self.applications.iter()
+            .filter(|application| aid.starts_with(&application.aid))
+            .max_by_key(|application| application.aid.len())
+    }
+
+    /// Certification authority public key of the payment system the AID belongs to
+    pub fn ca_key(&self, aid: &[u8], index: u8) -> Option<&CertificationAuthorityKey> {
+        self.ca_keys.iter().find(|key| aid.starts_with(&key.rid) && key.index[0] == index)
+    }
+
+    pub fn floor_limit(&self, aid: &[u8]) -> u32 {
+        self.application(aid).and_then(|application| application.floor_limit).unwrap_or(self.floor_limit)
+    }
+}
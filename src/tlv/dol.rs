@@ -0,0 +1,82 @@
+use std::fmt;
+
+use crate::tlv::parser::Tag;
+use crate::utils::extension::Extendable;
+
+/// Entry of a Data Object List, naming a data object and the length its value takes in the concatenated data
+#[derive(Debug)]
+pub struct DataObject {
+    pub tag: u16,
+    pub length: usize,
+}
+
+/// Parses a Data Object List (EMV Book 3 §5.4), made of tags and lengths without values
+pub fn parse(dol: &[u8]) -> Result<Vec<DataObject>, &'static str> {
+    let mut entries = Vec::new();
+    let mut iter = dol.iter();
+
+    while let Some(&first_byte) = iter.next() {
+        let tag = if first_byte & 0x1F == 0x1F {
+            first_byte.extend(*iter.next().ok_or("DOL tag is truncated!")?)
+        } else {
+            u16::from(first_byte)
+        };
+        let length = match *iter.next().ok_or("DOL length is missing!")? {
+            0x81 => usize::from(*iter.next().ok_or("DOL length is truncated!")?),
+            length if length < 0x80 => usize::from(length),
+            _ => return Err("Invalid DOL length!"),
+        };
+        entries.push(DataObject { tag, length });
+    }
+    Ok(entries)
+}
+
+/// Splits data built from a DOL into the values of its data objects, in the order the DOL lists them
+pub fn split<'a>(dol: &'a [DataObject], data: &'a [u8]) -> Result<Vec<(&'a DataObject, &'a [u8])>, &'static str> {
+    let mut values = Vec::new();
+    let mut remainder = data;
+    for entry in dol {
+        if remainder.len() < entry.length {
+            return Err("Data is shorter than its DOL!");
+        }
+        let (value, rest) = remainder.split_at(entry.length);
+        values.push((entry, value));
+        remainder = rest;
+    }
+    Ok(values)
+}
+
+/// Builds the data requested by a DOL (EMV Book 3 §5.4), looking each value up by its tag. Missing values are filled
+/// with zeroes, while values of another length are padded or truncated: on the left for numeric data objects, whose
+/// digits are right justified, and on the right for the others.
+pub fn build<'a, F>(dol: &[DataObject], lookup: F) -> Vec<u8> where F: Fn(u16) -> Option<&'a [u8]> {
+    let mut data = Vec::new();
+    for entry in dol {
+        let value = lookup(entry.tag).unwrap_or(&[]);
+        let length = value.len();
+        match (is_numeric(entry.tag), length < entry.length) {
+            (true, true) => {
+                data.extend(vec![0; entry.length - length]);
+                data.extend(value);
+            }
+            (true, false) => data.extend(&value[length - entry.length..]),
+            (false, true) => {
+                data.extend(value);
+                data.extend(vec![0; entry.length - length]);
+            }
+            (false, false) => data.extend(&value[..entry.length]),
+        }
+    }
+    data
+}
+
+/// Terminal and transaction data objects of format n
+fn is_numeric(tag: u16) -> bool {
+    matches!(tag, 0x5F2A | 0x5F36 | 0x9A | 0x9C | 0x9F02 | 0x9F03 | 0x9F1A | 0x9F21 | 0x9F41)
+}
+
+impl fmt::Display for DataObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X} {} ({})", self.tag, Tag::new(self.tag), self.length)
+    }
+}
@@ -1 +1,2 @@
+pub mod dol;
 pub mod parser;
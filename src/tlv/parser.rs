@@ -1,8 +1,11 @@
 use crate::utils::extension::Extendable;
 use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Tag {
+    AdditionalTerminalCapabilities,
+    AmountAuthorised,
+    AmountOther,
     ApplicationCryptogram,
     ApplicationCurrencyCode,
     ApplicationEffectiveDate,
@@ -14,45 +17,115 @@ pub enum Tag {
     ApplicationPrimaryAccountNumber,
     ApplicationPrimaryAccountNumberSequenceNumber,
     ApplicationPriorityIndicator,
+    ApplicationTemplate,
     ApplicationTransactionCounter,
     ApplicationUsageControl,
+    ApplicationVersionNumber,
+    ApplicationVersionNumberTerminal,
+    AuthorisationResponseCode,
     CardholderVerificationMethodList,
+    CertificationAuthorityPublicKeyIndex,
+    CardholderVerificationMethodResults,
     CardRiskManagementDataObjectList1,
     CardRiskManagementDataObjectList2,
+    CardVerificationCode3Track1,
+    CardVerificationCode3Track2,
     CryptogramInformationData,
+    CustomerExclusiveData,
+    DataAuthenticationCode,
     DedicatedFileName,
+    DynamicDataAuthenticationDataObjectList,
     EMVProprietaryTemplate,
     FileControlInformationIssuerDiscretionaryData,
     FileControlInformationProprietaryTemplate,
     FileControlInformationTemplate,
+    IntegratedCircuitCardDynamicNumber,
+    IntegratedCircuitCardPublicKeyCertificate,
+    IntegratedCircuitCardPublicKeyExponent,
+    IntegratedCircuitCardPublicKeyRemainder,
     IssuerActionCodeDefault,
     IssuerActionCodeDenial,
     IssuerActionCodeOnline,
     IssuerApplicationData,
+    IssuerAuthenticationData,
     IssuerCodeTableIndex,
     IssuerCountryCode,
+    IssuerPublicKeyCertificate,
+    IssuerPublicKeyExponent,
+    IssuerPublicKeyRemainder,
+    IssuerScriptCommand,
+    IssuerScriptIdentifier,
+    IssuerScriptResults,
+    IssuerScriptTemplate1,
+    IssuerScriptTemplate2,
     LanguagePreference,
+    LastOnlineApplicationTransactionCounterRegister,
     LogEntry,
+    LogFormat,
+    LowerConsecutiveOfflineLimit,
+    MerchantNameAndLocation,
     PinTryCounter,
+    ProcessingOptionsDataObjectList,
+    ResponseMessageTemplateFormat1,
     ResponseMessageTemplateFormat2,
+    ShortFileIdentifier,
+    SignedDynamicApplicationData,
+    SignedStaticApplicationData,
     StaticDataAuthenticationTagList,
-    UnknownTag,
+    TerminalCapabilities,
+    TerminalCountryCode,
+    TerminalFloorLimit,
+    TerminalType,
+    TerminalVerificationResults,
+    Track2EquivalentData,
+    TransactionCurrencyCode,
+    TransactionDate,
+    TransactionStatusInformation,
+    TransactionTime,
+    TransactionType,
+    UnpredictableNumber,
+    UpperConsecutiveOfflineLimit,
+    Unknown(u16),
 }
 
 impl Tag {
+    /// Names a tag of one or two bytes
+    pub fn new(value: u16) -> Tag {
+        let tag = if value > 0xFF { Tag::from_u16(value) } else { Tag::from_u8(value as u8) };
+        tag.unwrap_or(Tag::Unknown(value))
+    }
+
     pub fn from_u8(value: u8) -> Option<Tag> {
         match value {
             0x50 => Some(Tag::ApplicationLabel),
+            0x57 => Some(Tag::Track2EquivalentData),
             0x5A => Some(Tag::ApplicationPrimaryAccountNumber),
+            0x61 => Some(Tag::ApplicationTemplate),
             0x6F => Some(Tag::FileControlInformationTemplate),
+            0x70 => Some(Tag::EMVProprietaryTemplate),
+            0x71 => Some(Tag::IssuerScriptTemplate1),
+            0x72 => Some(Tag::IssuerScriptTemplate2),
             0x77 => Some(Tag::ResponseMessageTemplateFormat2),
+            0x80 => Some(Tag::ResponseMessageTemplateFormat1),
             0x82 => Some(Tag::ApplicationInterchangeProfile),
             0x84 => Some(Tag::DedicatedFileName),
             0x87 => Some(Tag::ApplicationPriorityIndicator),
+            0x86 => Some(Tag::IssuerScriptCommand),
+            0x88 => Some(Tag::ShortFileIdentifier),
+            0x8A => Some(Tag::AuthorisationResponseCode),
             0x8C => Some(Tag::CardRiskManagementDataObjectList1),
             0x8D => Some(Tag::CardRiskManagementDataObjectList2),
             0x8E => Some(Tag::CardholderVerificationMethodList),
+            0x8F => Some(Tag::CertificationAuthorityPublicKeyIndex),
+            0x90 => Some(Tag::IssuerPublicKeyCertificate),
+            0x91 => Some(Tag::IssuerAuthenticationData),
+            0x92 => Some(Tag::IssuerPublicKeyRemainder),
+            0x93 => Some(Tag::SignedStaticApplicationData),
             0x94 => Some(Tag::ApplicationFileLocator),
+            0x95 => Some(Tag::TerminalVerificationResults),
+            0x9A => Some(Tag::TransactionDate),
+            0x9B => Some(Tag::TransactionStatusInformation),
+            0x9C => Some(Tag::TransactionType),
             0xA5 => Some(Tag::FileControlInformationProprietaryTemplate),
             _ => None
         }
@@ -63,38 +136,65 @@ impl Tag {
             0x5F24 => Some(Tag::ApplicationExpirationDate),
             0x5F25 => Some(Tag::ApplicationEffectiveDate),
             0x5F28 => Some(Tag::IssuerCountryCode),
+            0x5F2A => Some(Tag::TransactionCurrencyCode),
             0x5F2D => Some(Tag::LanguagePreference),
             0x5F34 => Some(Tag::ApplicationPrimaryAccountNumberSequenceNumber),
-            0x7081 => Some(Tag::EMVProprietaryTemplate),
+            0x9F02 => Some(Tag::AmountAuthorised),
+            0x9F03 => Some(Tag::AmountOther),
             0x9F07 => Some(Tag::ApplicationUsageControl),
+            0x9F08 => Some(Tag::ApplicationVersionNumber),
+            0x9F09 => Some(Tag::ApplicationVersionNumberTerminal),
             0x9F0D => Some(Tag::IssuerActionCodeDefault),
             0x9F0E => Some(Tag::IssuerActionCodeDenial),
             0x9F0F => Some(Tag::IssuerActionCodeOnline),
             0x9F10 => Some(Tag::IssuerApplicationData),
             0x9F11 => Some(Tag::IssuerCodeTableIndex),
             0x9F12 => Some(Tag::ApplicationPreferredName),
+            0x9F13 => Some(Tag::LastOnlineApplicationTransactionCounterRegister),
+            0x9F14 => Some(Tag::LowerConsecutiveOfflineLimit),
             0x9F17 => Some(Tag::PinTryCounter),
+            0x9F18 => Some(Tag::IssuerScriptIdentifier),
+            0x9F1A => Some(Tag::TerminalCountryCode),
+            0x9F1B => Some(Tag::TerminalFloorLimit),
+            0x9F21 => Some(Tag::TransactionTime),
+            0x9F23 => Some(Tag::UpperConsecutiveOfflineLimit),
             0x9F26 => Some(Tag::ApplicationCryptogram),
             0x9F27 => Some(Tag::CryptogramInformationData),
+            0x9F32 => Some(Tag::IssuerPublicKeyExponent),
+            0x9F33 => Some(Tag::TerminalCapabilities),
+            0x9F34 => Some(Tag::CardholderVerificationMethodResults),
+            0x9F35 => Some(Tag::TerminalType),
             0x9F36 => Some(Tag::ApplicationTransactionCounter),
+            0x9F37 => Some(Tag::UnpredictableNumber),
+            0x9F38 => Some(Tag::ProcessingOptionsDataObjectList),
+            0x9F40 => Some(Tag::AdditionalTerminalCapabilities),
             0x9F42 => Some(Tag::ApplicationCurrencyCode),
+            0x9F45 => Some(Tag::DataAuthenticationCode),
+            0x9F46 => Some(Tag::IntegratedCircuitCardPublicKeyCertificate),
+            0x9F47 => Some(Tag::IntegratedCircuitCardPublicKeyExponent),
+            0x9F48 => Some(Tag::IntegratedCircuitCardPublicKeyRemainder),
+            0x9F49 => Some(Tag::DynamicDataAuthenticationDataObjectList),
             0x9F4A => Some(Tag::StaticDataAuthenticationTagList),
+            0x9F4B => Some(Tag::SignedDynamicApplicationData),
+            0x9F4C => Some(Tag::IntegratedCircuitCardDynamicNumber),
             0x9F4D => Some(Tag::LogEntry),
-            0x9F5D => Some(Tag::UnknownTag),
-            0x9F6E => Some(Tag::UnknownTag),
+            0x9F4E => Some(Tag::MerchantNameAndLocation),
+            0x9F4F => Some(Tag::LogFormat),
+            0x9F5B => Some(Tag::IssuerScriptResults),
+            0x9F60 => Some(Tag::CardVerificationCode3Track1),
+            0x9F61 => Some(Tag::CardVerificationCode3Track2),
+            0x9F7C => Some(Tag::CustomerExclusiveData),
             0xBF0C => Some(Tag::FileControlInformationIssuerDiscretionaryData),
             _ => None
         }
     }
+}
 
-    pub fn is_template(&self) -> bool {
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Tag::EMVProprietaryTemplate => true,
-            Tag::ResponseMessageTemplateFormat2 => true,
-            Tag::FileControlInformationTemplate => true,
-            Tag::FileControlInformationProprietaryTemplate => true,
-            Tag::FileControlInformationIssuerDiscretionaryData => true,
-            _ => false
+            Tag::Unknown(value) => write!(f, "Unknown({:02X})", value),
+            _ => write!(f, "{:?}", self),
         }
     }
 }
@@ -102,11 +202,25 @@ impl Tag {
 #[derive(Debug)]
 pub struct TLV {
     tag: Tag,
-    length: u8,
+    tag_number: u16,
+    length: usize,
     value: Vec<u8>,
+    constructed: bool,
 }
 
 impl TLV {
+    pub fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    pub fn tag_number(&self) -> u16 {
+        self.tag_number
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
     pub fn parse(data: Vec<u8>) -> Result<(TLV, Vec<u8>), &'static str> {
 
         if data.len() < 2 {
@@ -114,32 +228,73 @@ impl TLV {
         }
 
         let mut iter = data.iter();
-        let first_byte = iter.next().unwrap().clone();
-
-        Tag::from_u8(first_byte)
-            .or_else(|| { Tag::from_u16(first_byte.extend(iter.next().unwrap().clone())) })
-            .map_or(
-                Err("Unknown TLV tag!"),
-                |tag| {
-                    let length = iter.next().unwrap().clone();
-                    let value: Vec<u8> = iter.as_slice()[0..(usize::from(length))].to_vec();
-                    Ok((TLV { tag, length, value }, iter.as_slice()[(usize::from(length))..].to_vec()))
-                })
+        let first_byte = *iter.next().unwrap();
+        // Bit 6 of the first byte flags a constructed data object (template)
+        let constructed = first_byte & 0x20 == 0x20;
+
+        // Bits 1-5 all set mean the tag continues on the next byte
+        let tag_number = if first_byte & 0x1F == 0x1F {
+            let second_byte = *iter.next().ok_or("TLV tag is truncated!")?;
+            if second_byte & 0x80 == 0x80 {
+                return Err("TLV tags longer than two bytes are not supported!");
+            }
+            first_byte.extend(second_byte)
+        } else {
+            u16::from(first_byte)
+        };
+
+        let length = match *iter.next().ok_or("TLV length is missing!")? {
+            0x81 => usize::from(*iter.next().ok_or("TLV length is truncated!")?),
+            0x82 => {
+                let msb = *iter.next().ok_or("TLV length is truncated!")?;
+                let lsb = *iter.next().ok_or("TLV length is truncated!")?;
+                usize::from(msb.extend(lsb))
+            }
+            length if length < 0x80 => usize::from(length),
+            _ => return Err("Invalid TLV length!"),
+        };
+
+        let remainder = iter.as_slice();
+        if remainder.len() < length {
+            return Err("TLV value is truncated!");
+        }
+        let value = remainder[..length].to_vec();
+        let tag = Tag::new(tag_number);
+        Ok((TLV { tag, tag_number, length, value, constructed }, remainder[length..].to_vec()))
+    }
+
+    /// Encodes a BER-TLV data object, tags above 0xFF taking two bytes
+    pub fn encode(tag: u16, value: &[u8]) -> Vec<u8> {
+        let mut data = if tag > 0xFF { tag.to_be_bytes().to_vec() } else { vec![tag as u8] };
+        match value.len() {
+            length if length < 0x80 => data.push(length as u8),
+            length if length <= 0xFF => data.extend(&[0x81, length as u8]),
+            length => {
+                data.push(0x82);
+                data.extend(&(length as u16).to_be_bytes());
+            }
+        }
+        data.extend(value);
+        data
     }
 
+    /// Decodes every primitive data object, flattening the templates that contain them. Decoding stops at the first
+    /// malformed object, since what follows can't be delimited.
     pub fn decode(data: Vec<u8>) -> Vec<TLV> {
         let mut result: Vec<TLV> = Vec::new();
         let mut data = data;
 
-        if data.len() > 0 {
-            loop {
-                let (tlv, remainder) = TLV::parse(data).unwrap();
-                if !tlv.tag.is_template() {
-                    result.push(tlv);
-                    if remainder.len() == 0 {
-                        break;
-                    } else { data = remainder; }
-                } else { data = tlv.value; }
+        while !data.is_empty() {
+            match TLV::parse(data) {
+                Ok((tlv, remainder)) => {
+                    if tlv.constructed {
+                        result.extend(TLV::decode(tlv.value));
+                    } else {
+                        result.push(tlv);
+                    }
+                    data = remainder;
+                }
+                Err(_) => break,
             }
         }
         result
@@ -150,6 +305,6 @@ impl fmt::Display for TLV {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let raw_str: Vec<String> = self.value.iter().map(|a| format!("{:02X}", a)).collect();
         let value_char: Vec<String> = self.value.iter().map(|a| format!("{}", *a as char)).collect();
-        write!(f, "   > {:?} ({}) = 0x{} | {}\n", self.tag, self.length, raw_str.join(""), value_char.join(""))
+        writeln!(f, "   > {} ({}) = 0x{} | {}", self.tag, self.length, raw_str.join(""), value_char.join(""))
     }
 }
@@ -0,0 +1,91 @@
+//! Terminal action analysis (EMV Book 3 §10.7): the TVR is compared with the Terminal and Issuer Action Codes to
+//! decline the transaction offline, send it online or approve it offline
+use crate::apdu::capdu::CryptogramType;
+use crate::session::EmvSession;
+use crate::terminal::ApplicationProfile;
+use crate::transaction::tvr::Tvr;
+
+/// Action codes of the issuer and the terminal for one of the decisions
+struct ActionCodes {
+    name: &'static str,
+    issuer: Vec<u8>,
+    terminal: Vec<u8>,
+}
+
+impl ActionCodes {
+    fn denial(session: &EmvSession) -> ActionCodes {
+        ActionCodes::new(session, "Denial", 0x9F0E, 0x00, |application| application.tac_denial)
+    }
+
+    fn online(session: &EmvSession) -> ActionCodes {
+        ActionCodes::new(session, "Online", 0x9F0F, 0xFF, |application| application.tac_online)
+    }
+
+    fn default(session: &EmvSession) -> ActionCodes {
+        ActionCodes::new(session, "Default", 0x9F0D, 0xFF, |application| application.tac_default)
+    }
+
+    /// Missing IACs take the filler given, while missing TACs are zeros
+    fn new<F>(session: &EmvSession, name: &'static str, iac: u16, filler: u8, tac: F) -> ActionCodes
+        where F: Fn(&ApplicationProfile) -> [u8; 5] {
+        let issuer = session.card(iac).map_or(vec![filler; 5], <[u8]>::to_vec);
+        let terminal = session.application()
+            .and_then(|aid| session.profile().application(aid))
+            .map_or(vec![0x00; 5], |application| tac(application).to_vec());
+        ActionCodes { name, issuer, terminal }
+    }
+
+    /// TVR bits matching either action code, each along with the codes it matched
+    fn matches(&self, tvr: &[u8]) -> Vec<String> {
+        Tvr::decode(tvr).into_iter()
+            .filter_map(|bit| {
+                let (byte, mask) = bit.position();
+                let codes: Vec<String> = [("IAC", &self.issuer), ("TAC", &self.terminal)].iter()
+                    .filter(|(_, code)| code.get(byte).is_some_and(|value| value & mask != 0))
+                    .map(|(code, _)| format!("{}-{}", code, self.name))
+                    .collect();
+                if codes.is_empty() { None } else { Some(format!("{} ({})", bit, codes.join(", "))) }
+            })
+            .collect()
+    }
+}
+
+/// Chooses the cryptogram to request in the first GENERATE AC, explaining which TVR bits led to it
+pub fn analyse(session: &EmvSession) -> (CryptogramType, Vec<String>) {
+    let tvr = session.terminal(0x95).unwrap_or_default().to_vec();
+    let mut notes = vec![format!("TVR {}", hex::encode_upper(&tvr))];
+
+    let denial = ActionCodes::denial(session).matches(&tvr);
+    if !denial.is_empty() {
+        notes.extend(denial.into_iter().map(|bit| format!("Declining offline: {}", bit)));
+        return (CryptogramType::AAC, notes);
+    }
+
+    if !online_capable(session) {
+        let (cryptogram_type, default) = default_action(session);
+        notes.extend(default);
+        return (cryptogram_type, notes);
+    }
+    let online = ActionCodes::online(session).matches(&tvr);
+    if online.is_empty() {
+        notes.push(String::from("Approving offline: no TVR bit matches the denial or online action codes"));
+        return (CryptogramType::TC, notes);
+    }
+    notes.extend(online.into_iter().map(|bit| format!("Going online: {}", bit)));
+    (CryptogramType::ARQC, notes)
+}
+
+/// Decision of terminals that can't go online, whether offline only or unable to reach the issuer
+pub fn default_action(session: &EmvSession) -> (CryptogramType, Vec<String>) {
+    let tvr = session.terminal(0x95).unwrap_or_default();
+    let default = ActionCodes::default(session).matches(tvr);
+    if default.is_empty() {
+        return (CryptogramType::TC, vec![String::from("Approving offline: no TVR bit matches the default action codes")]);
+    }
+    (CryptogramType::AAC, default.into_iter().map(|bit| format!("Declining offline by default: {}", bit)).collect())
+}
+
+/// Online only terminals and offline terminals with online capability, as told by the Terminal Type (9F35)
+fn online_capable(session: &EmvSession) -> bool {
+    session.terminal(0x9F35).and_then(|value| value.first()).is_some_and(|value| matches!(value & 0x0F, 1 | 2 | 4 | 5))
+}
@@ -0,0 +1,255 @@
+//! Cardholder verification (EMV Book 3 §10.5): the first CV Rule of the CVM List (8E) whose condition applies is
+//! performed, moving on to the next one when it fails and the rule allows it
+use crate::apdu::capdu;
+use crate::apdu::rapdu::{Status, StatusKind};
+use crate::connection::Transport;
+use crate::controller;
+use crate::error::EmvError;
+use crate::session::EmvSession;
+use crate::transaction::{Interaction, amount};
+use crate::transaction::oda::{self, PublicKey};
+use crate::transaction::tvr::{Tsi, Tvr};
+
+/// Cardholder Verification Methods, in the low six bits of the first byte of a CV Rule
+const FAIL: u8 = 0x00;
+const PLAINTEXT_PIN: u8 = 0x01;
+const ENCIPHERED_PIN_ONLINE: u8 = 0x02;
+const PLAINTEXT_PIN_AND_SIGNATURE: u8 = 0x03;
+const ENCIPHERED_PIN: u8 = 0x04;
+const ENCIPHERED_PIN_AND_SIGNATURE: u8 = 0x05;
+const SIGNATURE: u8 = 0x1E;
+const NO_CVM: u8 = 0x1F;
+
+/// Moving on to the next CV Rule when this one fails
+const APPLY_SUCCEEDING_RULE: u8 = 0x40;
+
+/// Result byte of the CVM Results (9F34)
+const UNKNOWN: u8 = 0x00;
+const FAILED: u8 = 0x01;
+const SUCCESSFUL: u8 = 0x02;
+
+/// No CVM performed, as recorded in the CVM Results when no CV Rule applies
+const NO_CVM_PERFORMED: u8 = 0x3F;
+
+/// Performs cardholder verification, recording the CVM Results (9F34) along with the TVR and TSI bits
+pub fn process(card: &dyn Transport, session: &mut EmvSession, interaction: &dyn Interaction) -> Result<Vec<String>, EmvError> {
+    // Amounts X and Y precede the CV Rules
+    let list = match session.card(0x8E) {
+        Some(list) if list.len() >= 10 => list.to_vec(),
+        _ => {
+            session.set_tvr(Tvr::IccDataMissing);
+            session.set_terminal(0x9F34, vec![NO_CVM_PERFORMED, 0x00, UNKNOWN]);
+            return Ok(vec![String::from("The CVM List is missing, no CVM performed")]);
+        }
+    };
+    let x = u32::from_be_bytes([list[0], list[1], list[2], list[3]]);
+    let y = u32::from_be_bytes([list[4], list[5], list[6], list[7]]);
+    session.set_tsi(Tsi::CardholderVerificationPerformed);
+
+    let mut notes = Vec::new();
+    for rule in list[8..].chunks_exact(2) {
+        let (method, condition) = (rule[0], rule[1]);
+        let cvm = method & 0x3F;
+        match satisfied(session, condition, cvm, x, y) {
+            Some(true) => {}
+            Some(false) => continue,
+            None => {
+                notes.push(format!("Rule {:02X}{:02X}: unknown condition, skipped", method, condition));
+                continue;
+            }
+        }
+
+        notes.push(format!("Rule {:02X}{:02X}: {}", method, condition, describe(cvm)));
+        if let Some(result) = perform(card, session, interaction, cvm, &mut notes)? {
+            session.set_terminal(0x9F34, vec![method, condition, result]);
+            return Ok(notes);
+        }
+        if method & APPLY_SUCCEEDING_RULE == 0 || cvm == FAIL {
+            session.set_tvr(Tvr::CardholderVerificationFailed);
+            session.set_terminal(0x9F34, vec![method, condition, FAILED]);
+            notes.push(String::from("Cardholder verification failed"));
+            return Ok(notes);
+        }
+    }
+
+    session.set_tvr(Tvr::CardholderVerificationFailed);
+    session.set_terminal(0x9F34, vec![NO_CVM_PERFORMED, 0x00, FAILED]);
+    notes.push(String::from("No CV Rule could be performed, cardholder verification failed"));
+    Ok(notes)
+}
+
+/// Whether the condition of the CV Rule applies to the transaction, none when it isn't known
+fn satisfied(session: &EmvSession, condition: u8, cvm: u8, x: u32, y: u32) -> Option<bool> {
+    let transaction_type = session.terminal(0x9C).and_then(|value| value.first()).copied().unwrap_or_default();
+    let cash = transaction_type == 0x01;
+    let cashback = transaction_type == 0x09 || amount(session, 0x9F03) > 0;
+    let unattended = session.terminal(0x9F35).and_then(|value| value.first()).is_some_and(|value| matches!(value & 0x0F, 4..=6));
+
+    match condition {
+        0x00 => Some(true),
+        0x01 => Some(unattended && cash),
+        0x02 => Some(!cash && !cashback),
+        0x03 => Some(terminal_supports(session, cvm)),
+        0x04 => Some(!unattended && cash),
+        0x05 => Some(cashback),
+        0x06..=0x09 => {
+            // Amounts X and Y are in the application currency, which the transaction must be in for them to apply
+            if session.terminal(0x5F2A).is_none() || session.terminal(0x5F2A) != session.card(0x9F42) {
+                return Some(false);
+            }
+            let amount = amount(session, 0x9F02);
+            Some(match condition {
+                0x06 => amount < u64::from(x),
+                0x07 => amount > u64::from(x),
+                0x08 => amount < u64::from(y),
+                _ => amount > u64::from(y),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Performs the CVM, returning the result byte of the CVM Results when it succeeded
+fn perform(card: &dyn Transport, session: &mut EmvSession, interaction: &dyn Interaction, cvm: u8, notes: &mut Vec<String>) -> Result<Option<u8>, EmvError> {
+    let supported = terminal_supports(session, cvm);
+    match cvm {
+        FAIL => Ok(None),
+        PLAINTEXT_PIN | PLAINTEXT_PIN_AND_SIGNATURE if supported => {
+            if !offline_pin(card, session, interaction, None, notes)? {
+                return Ok(None);
+            }
+            // The signature is checked by the merchant once the receipt is printed
+            Ok(Some(if cvm == PLAINTEXT_PIN { SUCCESSFUL } else { UNKNOWN }))
+        }
+        ENCIPHERED_PIN_ONLINE if supported => match interaction.pin(None) {
+            Some(_) => {
+                session.set_tvr(Tvr::OnlinePinEntered);
+                notes.push(String::from("PIN entered, to be verified by the issuer"));
+                Ok(Some(UNKNOWN))
+            }
+            None => {
+                session.set_tvr(Tvr::PinNotEntered);
+                notes.push(String::from("PIN entry bypassed"));
+                Ok(None)
+            }
+        },
+        ENCIPHERED_PIN | ENCIPHERED_PIN_AND_SIGNATURE if supported => {
+            let key = match oda::pin_key(session, notes) {
+                Ok(key) => key,
+                Err(reason) => {
+                    notes.push(format!("The PIN can't be enciphered: {}", reason));
+                    return Ok(None);
+                }
+            };
+            if !offline_pin(card, session, interaction, Some(&key), notes)? {
+                return Ok(None);
+            }
+            Ok(Some(if cvm == ENCIPHERED_PIN { SUCCESSFUL } else { UNKNOWN }))
+        }
+        SIGNATURE if supported => {
+            notes.push(String::from("Signature required on the receipt"));
+            Ok(Some(UNKNOWN))
+        }
+        NO_CVM if supported => Ok(Some(SUCCESSFUL)),
+        PLAINTEXT_PIN | PLAINTEXT_PIN_AND_SIGNATURE | ENCIPHERED_PIN_ONLINE | ENCIPHERED_PIN | ENCIPHERED_PIN_AND_SIGNATURE => {
+            session.set_tvr(Tvr::PinPadNotPresent);
+            notes.push(String::from("The terminal has no PIN pad for this CVM"));
+            Ok(None)
+        }
+        SIGNATURE | NO_CVM => {
+            notes.push(String::from("The terminal doesn't support this CVM"));
+            Ok(None)
+        }
+        _ => {
+            session.set_tvr(Tvr::UnrecognisedCvm);
+            Ok(None)
+        }
+    }
+}
+
+/// Verifies the PIN with the card, asking the cardholder again after a wrong PIN while tries remain. With a key, the PIN
+/// block is enciphered along with a challenge from the card.
+fn offline_pin(card: &dyn Transport, session: &mut EmvSession, interaction: &dyn Interaction, key: Option<&PublicKey>, notes: &mut Vec<String>) -> Result<bool, EmvError> {
+    // The PIN Try Counter avoids sending a PIN the card would reject, when the card discloses it
+    let mut tries_remaining = controller::pin_try_counter(card).ok();
+    loop {
+        if tries_remaining == Some(0) {
+            session.set_tvr(Tvr::PinTryLimitExceeded);
+            notes.push(String::from("PIN Try Limit exceeded"));
+            return Ok(false);
+        }
+        let pin = match interaction.pin(tries_remaining) {
+            Some(pin) => pin,
+            None => {
+                session.set_tvr(Tvr::PinNotEntered);
+                notes.push(String::from("PIN entry bypassed"));
+                return Ok(false);
+            }
+        };
+        let pin_block = match capdu::pin_block(&pin) {
+            Ok(pin_block) => pin_block,
+            Err(err) => {
+                notes.push(String::from(err));
+                continue;
+            }
+        };
+        let pin_data = match key {
+            Some(key) => match controller::get_challenge(card) {
+                Ok(challenge) => oda::encipher_pin(key, &pin_block, &challenge),
+                Err(EmvError::Status(status)) => {
+                    notes.push(format!("The card gave no challenge to encipher the PIN: {}", status));
+                    return Ok(false);
+                }
+                Err(err) => return Err(err),
+            },
+            None => pin_block,
+        };
+        match controller::verify(card, key.is_some(), pin_data) {
+            Ok(_) => {
+                notes.push(String::from("PIN verified by the card"));
+                return Ok(true);
+            }
+            Err(EmvError::Status(Status { kind: StatusKind::WrongPin { tries_remaining: remaining }, .. })) => {
+                notes.push(format!("Wrong PIN, {} tries remaining", remaining));
+                tries_remaining = Some(remaining);
+            }
+            Err(EmvError::Status(Status { kind: StatusKind::PinBlocked, .. })) => tries_remaining = Some(0),
+            Err(EmvError::Status(status)) => {
+                notes.push(format!("The card rejected the PIN: {}", status));
+                return Ok(false);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Whether the Terminal Capabilities (9F33) second byte lists the CVM
+fn terminal_supports(session: &EmvSession, cvm: u8) -> bool {
+    let capabilities = session.terminal(0x9F33).and_then(|value| value.get(1)).copied().unwrap_or_default();
+    let required = match cvm {
+        FAIL => 0x00,
+        PLAINTEXT_PIN => 0x80,
+        ENCIPHERED_PIN_ONLINE => 0x40,
+        PLAINTEXT_PIN_AND_SIGNATURE => 0x80 | 0x20,
+        ENCIPHERED_PIN => 0x10,
+        ENCIPHERED_PIN_AND_SIGNATURE => 0x10 | 0x20,
+        SIGNATURE => 0x20,
+        NO_CVM => 0x08,
+        _ => return false,
+    };
+    capabilities & required == required
+}
+
+fn describe(cvm: u8) -> &'static str {
+    match cvm {
+        FAIL => "fail CVM processing",
+        PLAINTEXT_PIN => "plaintext PIN verified by the ICC",
+        ENCIPHERED_PIN_ONLINE => "enciphered PIN verified online",
+        PLAINTEXT_PIN_AND_SIGNATURE => "plaintext PIN verified by the ICC and signature",
+        ENCIPHERED_PIN => "enciphered PIN verified by the ICC",
+        ENCIPHERED_PIN_AND_SIGNATURE => "enciphered PIN verified by the ICC and signature",
+        SIGNATURE => "signature",
+        NO_CVM => "no CVM required",
+        _ => "unrecognised CVM",
+    }
+}
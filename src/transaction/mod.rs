@@ -0,0 +1,357 @@
+//! Contact transaction flow of EMV Book 3 §10, run phase by phase over an [`EmvSession`]. Every phase records its
+//! outcome in the session's TVR (95) and TSI (9B), which are reported along with notes on what was done.
+use crate::apdu::capdu::CryptogramType;
+use crate::connection::Transport;
+use crate::controller;
+use crate::error::EmvError;
+use crate::session::{EmvSession, Phase};
+use crate::transaction::online::AuthorisationResponse;
+use crate::transaction::tvr::{Tsi, Tvr};
+
+pub mod action;
+pub mod cvm;
+pub mod oda;
+pub mod online;
+pub mod restrictions;
+pub mod risk;
+pub mod tvr;
+
+/// Phases the transaction goes through, in order
+const PHASES: [Phase; 11] = [
+    Phase::ApplicationSelection,
+    Phase::InitiateApplicationProcessing,
+    Phase::ReadApplicationData,
+    Phase::OfflineDataAuthentication,
+    Phase::ProcessingRestrictions,
+    Phase::CardholderVerification,
+    Phase::TerminalRiskManagement,
+    Phase::TerminalActionAnalysis,
+    Phase::CardActionAnalysis,
+    Phase::OnlineProcessing,
+    Phase::Completion,
+];
+
+/// Functions of the Application Interchange Profile (82) first byte
+const AIP_SDA: u8 = 0x40;
+const AIP_DDA: u8 = 0x20;
+const AIP_CARDHOLDER_VERIFICATION: u8 = 0x10;
+const AIP_TERMINAL_RISK_MANAGEMENT: u8 = 0x08;
+const AIP_ISSUER_AUTHENTICATION: u8 = 0x04;
+const AIP_CDA: u8 = 0x01;
+
+/// Authorisation Response Code of the offline decline, when the card asked to go online but CDA failed
+const CDA_DECLINED: &[u8; 2] = b"Z1";
+
+/// What the transaction needs from the people at the terminal
+pub trait Interaction {
+    /// Asks the cardholder for the PIN digits, none when they bypass the PIN entry
+    fn pin(&self, tries_remaining: Option<u8>) -> Option<String>;
+    /// Sends the authorisation request to the issuer, none when it can't be reached
+    fn authorise(&self, session: &EmvSession) -> Option<AuthorisationResponse>;
+}
+
+/// Outcome of a phase: what was done and the TVR and TSI bits it set
+pub struct Step {
+    pub phase: Phase,
+    pub notes: Vec<String>,
+    pub tvr: Vec<Tvr>,
+    pub tsi: Vec<Tsi>,
+}
+
+/// Transaction with the application, run one phase at a time
+pub struct Transaction {
+    aid: Vec<u8>,
+    next: usize,
+    cryptogram_type: CryptogramType,
+    returned: Option<CryptogramType>,
+    authorisation: Option<AuthorisationResponse>,
+    /// ICC public key verifying the GENERATE AC responses, when CDA is performed
+    icc_key: Option<oda::PublicKey>,
+}
+
+impl Transaction {
+    pub fn new(aid: Vec<u8>) -> Transaction {
+        Transaction {
+            aid,
+            next: 0,
+            cryptogram_type: CryptogramType::ARQC,
+            returned: None,
+            authorisation: None,
+            icc_key: None,
+        }
+    }
+
+    /// Phase the next step runs, none once the transaction is over
+    pub fn next_phase(&self) -> Option<Phase> {
+        PHASES.get(self.next).copied()
+    }
+
+    /// Runs the next phase, the transaction being terminated when it fails
+    pub fn step(&mut self, card: &dyn Transport, session: &mut EmvSession, interaction: &dyn Interaction) -> Result<Step, EmvError> {
+        let phase = self.next_phase().ok_or(EmvError::Terminated("The transaction is already over"))?;
+        let result = run(phase, session, |session| match phase {
+            Phase::ApplicationSelection => application_selection(card, session, &self.aid),
+            Phase::InitiateApplicationProcessing => self.initiate_application_processing(card, session),
+            Phase::ReadApplicationData => self.read_application_data(card, session),
+            Phase::OfflineDataAuthentication => self.offline_data_authentication(card, session),
+            Phase::ProcessingRestrictions => Ok(restrictions::check(session)),
+            Phase::CardholderVerification => cardholder_verification(card, session, interaction),
+            Phase::TerminalRiskManagement => terminal_risk_management(card, session),
+            Phase::TerminalActionAnalysis => self.terminal_action_analysis(session),
+            Phase::CardActionAnalysis => self.card_action_analysis(card, session),
+            Phase::OnlineProcessing => self.online_processing(card, session, interaction),
+            Phase::Completion => self.completion(card, session),
+            _ => Ok(vec![]),
+        });
+        // A failed phase terminates the transaction
+        self.next = if result.is_ok() { self.next + 1 } else { PHASES.len() };
+        result
+    }
+
+    fn initiate_application_processing(&mut self, card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+        let pdol_data = session.pdol_data();
+        let response = controller::get_processing_options(card, pdol_data.clone())?;
+        session.record_processing_options(&pdol_data, &response);
+        if session.card(0x82).is_none() || session.card(0x94).is_none() {
+            return Err(EmvError::Terminated("The card didn't provide the AIP and the AFL"));
+        }
+
+        let functions: Vec<&str> = [
+            (AIP_SDA, "SDA"),
+            (AIP_DDA, "DDA"),
+            (AIP_CDA, "CDA"),
+            (AIP_CARDHOLDER_VERIFICATION, "cardholder verification"),
+            (AIP_TERMINAL_RISK_MANAGEMENT, "terminal risk management"),
+            (AIP_ISSUER_AUTHENTICATION, "issuer authentication"),
+        ].iter()
+            .filter(|(mask, _)| supports(session, *mask))
+            .map(|(_, function)| *function)
+            .collect();
+        Ok(vec![format!("The card supports {}", if functions.is_empty() { String::from("none of the functions") } else { functions.join(", ") })])
+    }
+
+    /// Reads the records listed in the AFL, each entry naming the SFI, the first and last records and how many of them
+    /// take part in offline data authentication
+    fn read_application_data(&mut self, card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+        let afl = session.card(0x94).unwrap_or_default().to_vec();
+        if afl.is_empty() || !afl.len().is_multiple_of(4) {
+            return Err(EmvError::Terminated("The AFL is malformed"));
+        }
+
+        let mut notes = Vec::new();
+        for entry in afl.chunks(4) {
+            let (sfi, first, last, authenticated) = (entry[0] >> 3, entry[1], entry[2], entry[3]);
+            if !(1..=30).contains(&sfi) || first == 0 || last < first || authenticated > last - first + 1 {
+                return Err(EmvError::Terminated("The AFL is malformed"));
+            }
+            for record in first..=last {
+                let response = controller::read_record(card, record, sfi << 3 | 0x04)?;
+                session.record_read(sfi, record, &response);
+            }
+            notes.push(format!("Read records {} to {} of SFI {}, {} for offline data authentication", first, last, sfi, authenticated));
+        }
+
+        // Expiration date, PAN, CDOL1 and CDOL2
+        if [0x5F24, 0x5A, 0x8C, 0x8D].iter().any(|tag| session.card(*tag).is_none()) {
+            return Err(EmvError::Terminated("Mandatory data objects are missing from the records"));
+        }
+        Ok(notes)
+    }
+
+    /// Chooses CDA, then DDA, then SDA, among the methods both the card and the terminal support
+    fn offline_data_authentication(&mut self, card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+        let capabilities = session.terminal(0x9F33).and_then(|value| value.get(2)).copied().unwrap_or_default();
+        let method = [(AIP_CDA, 0x08, "CDA"), (AIP_DDA, 0x40, "DDA"), (AIP_SDA, 0x80, "SDA")].iter()
+            .find(|(aip, capability, _)| supports(session, *aip) && capabilities & capability != 0)
+            .map(|(_, _, method)| *method);
+
+        match method {
+            Some("CDA") => {
+                session.set_tsi(Tsi::OfflineDataAuthenticationPerformed);
+                let (icc_key, mut notes) = oda::cda_key(session);
+                if icc_key.is_some() {
+                    notes.push(String::from("CDA is requested by the GENERATE AC commands for TCs and ARQCs"));
+                }
+                self.icc_key = icc_key;
+                Ok(notes)
+            }
+            Some("DDA") => {
+                session.set_tsi(Tsi::OfflineDataAuthenticationPerformed);
+                oda::dda(card, session)
+            }
+            Some("SDA") => {
+                session.set_tvr(Tvr::StaticDataAuthenticationSelected);
+                session.set_tsi(Tsi::OfflineDataAuthenticationPerformed);
+                Ok(oda::sda(session))
+            }
+            _ => {
+                session.set_tvr(Tvr::OfflineDataAuthenticationNotPerformed);
+                Ok(vec![String::from("The card and the terminal have no authentication method in common")])
+            }
+        }
+    }
+
+    /// Decides on the cryptogram the first GENERATE AC requests
+    fn terminal_action_analysis(&mut self, session: &EmvSession) -> Result<Vec<String>, EmvError> {
+        let (cryptogram_type, mut notes) = action::analyse(session);
+        self.cryptogram_type = cryptogram_type;
+        notes.push(format!("Requesting {:?}", cryptogram_type));
+        Ok(notes)
+    }
+
+    /// Requests the first cryptogram with the CDOL1 data, the card deciding whether to go along with the terminal
+    fn card_action_analysis(&mut self, card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+        let cda_notes = self.generate_ac(card, session, self.cryptogram_type, true)?;
+        session.set_tsi(Tsi::CardRiskManagementPerformed);
+
+        // The card may go from a TC down to an ARQC or an AAC, but never up
+        let (returned, cryptogram_notes) = cryptogram(session)?;
+        let rank = |cryptogram_type| match cryptogram_type {
+            CryptogramType::AAC => 0,
+            CryptogramType::ARQC => 1,
+            CryptogramType::TC => 2,
+        };
+        if rank(returned) > rank(self.cryptogram_type) {
+            return Err(EmvError::Terminated("The card returned a higher cryptogram than requested"));
+        }
+        self.returned = Some(returned);
+
+        let mut notes = vec![format!("Requested {:?}{}, the card returned {:?} {}", self.cryptogram_type, self.cda_note(self.cryptogram_type),
+                                     returned, hex::encode_upper(session.card(0x9F26).unwrap_or_default()))];
+        notes.extend(cryptogram_notes);
+        notes.extend(cda_notes);
+        if returned == CryptogramType::TC && self.cda_failed(session) {
+            // The card approved offline, but its cryptogram can't be trusted
+            self.returned = Some(CryptogramType::AAC);
+            notes.push(String::from("The TC is declined as CDA failed"));
+            return Ok(notes);
+        }
+        notes.push(String::from(match returned {
+            CryptogramType::TC => "The card approves the transaction offline",
+            CryptogramType::AAC => "The card declines the transaction offline",
+            CryptogramType::ARQC => "The card asks to go online",
+        }));
+        Ok(notes)
+    }
+
+    fn online_processing(&mut self, card: &dyn Transport, session: &mut EmvSession, interaction: &dyn Interaction) -> Result<Vec<String>, EmvError> {
+        if self.returned != Some(CryptogramType::ARQC) {
+            return Ok(vec![String::from("The card didn't ask to go online")]);
+        }
+        if self.cda_failed(session) {
+            session.set_terminal(0x8A, CDA_DECLINED.to_vec());
+            return Ok(vec![String::from("CDA failed, the transaction is declined offline with Authorisation Response Code Z1")]);
+        }
+        let (authorisation, notes) = online::authorise(card, session, interaction)?;
+        self.authorisation = authorisation;
+        Ok(notes)
+    }
+
+    fn completion(&mut self, card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+        match self.returned {
+            Some(CryptogramType::ARQC) => online::complete(self, card, session),
+            Some(CryptogramType::TC) => Ok(vec![String::from("Transaction approved offline")]),
+            _ => Ok(vec![String::from("Transaction declined offline")]),
+        }
+    }
+
+    /// Sends the GENERATE AC with the CDOL1 or CDOL2 data, requesting CDA for a TC or an ARQC when the ICC public key
+    /// was recovered. The notes are those of CDA.
+    fn generate_ac(&mut self, card: &dyn Transport, session: &mut EmvSession, cryptogram_type: CryptogramType, first: bool) -> Result<Vec<String>, EmvError> {
+        let cdol_data = session.cdol_data(first)
+            .ok_or(EmvError::Terminated(if first { "The card didn't provide the CDOL1" } else { "The card didn't provide the CDOL2" }))?;
+        let icc_key = self.icc_key.as_ref().filter(|_| cryptogram_type != CryptogramType::AAC);
+        let response = controller::generate_ac(card, cryptogram_type, icc_key.is_some(), cdol_data.clone())?;
+        session.record_cryptogram(&cdol_data, &response);
+        Ok(icc_key.map_or_else(Vec::new, |icc_key| oda::cda(session, icc_key, &response)))
+    }
+
+    fn cda_note(&self, cryptogram_type: CryptogramType) -> &'static str {
+        if self.icc_key.is_some() && cryptogram_type != CryptogramType::AAC { " with CDA" } else { "" }
+    }
+
+    /// Whether the signature of the last cryptogram failed to verify, the ICC public key being recovered beforehand
+    fn cda_failed(&self, session: &EmvSession) -> bool {
+        self.icc_key.is_some() && Tvr::decode(session.terminal(0x95).unwrap_or_default()).contains(&Tvr::CombinedDataAuthenticationFailed)
+    }
+}
+
+/// Checks the processing restrictions outside of a transaction, over the data read from the card so far
+pub fn check_restrictions(session: &mut EmvSession) -> Step {
+    let notes = restrictions::check(session);
+    report(Phase::ProcessingRestrictions, session, notes, &[], &[])
+}
+
+/// Runs a phase over the session, reporting the TVR and TSI bits it set
+fn run<F>(phase: Phase, session: &mut EmvSession, process: F) -> Result<Step, EmvError>
+    where F: FnOnce(&mut EmvSession) -> Result<Vec<String>, EmvError> {
+    let tvr = Tvr::decode(session.terminal(0x95).unwrap_or_default());
+    let tsi = Tsi::decode(session.terminal(0x9B).unwrap_or_default());
+    let notes = process(session)?;
+    session.advance(phase);
+    Ok(report(phase, session, notes, &tvr, &tsi))
+}
+
+fn report(phase: Phase, session: &EmvSession, notes: Vec<String>, tvr: &[Tvr], tsi: &[Tsi]) -> Step {
+    Step {
+        phase,
+        notes,
+        tvr: Tvr::decode(session.terminal(0x95).unwrap_or_default()).into_iter().filter(|bit| !tvr.contains(bit)).collect(),
+        tsi: Tsi::decode(session.terminal(0x9B).unwrap_or_default()).into_iter().filter(|bit| !tsi.contains(bit)).collect(),
+    }
+}
+
+fn application_selection(card: &dyn Transport, session: &mut EmvSession, aid: &[u8]) -> Result<Vec<String>, EmvError> {
+    let response = controller::select_application(card, aid.to_vec())?;
+    session.select(aid.to_vec(), &response);
+    let label = session.card(0x50).map_or(String::new(), |label| String::from_utf8_lossy(label).into_owned());
+    Ok(vec![format!("Selected {} {}", hex::encode_upper(session.application().unwrap_or_default()), label)])
+}
+
+fn cardholder_verification(card: &dyn Transport, session: &mut EmvSession, interaction: &dyn Interaction) -> Result<Vec<String>, EmvError> {
+    if !supports(session, AIP_CARDHOLDER_VERIFICATION) {
+        return Ok(vec![String::from("The card doesn't support cardholder verification")]);
+    }
+    cvm::process(card, session, interaction)
+}
+
+fn terminal_risk_management(card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+    if !supports(session, AIP_TERMINAL_RISK_MANAGEMENT) {
+        return Ok(vec![String::from("The card doesn't ask for terminal risk management")]);
+    }
+    risk::manage(card, session)
+}
+
+/// Cryptogram the card returned, as told by the Cryptogram Information Data (9F27), with the advice and reason it gives
+fn cryptogram(session: &EmvSession) -> Result<(CryptogramType, Vec<String>), EmvError> {
+    let cid = session.card(0x9F27).and_then(|value| value.first()).copied()
+        .ok_or(EmvError::Parse("The Cryptogram Information Data is missing from the response!"))?;
+    let mut notes = Vec::new();
+    let returned = CryptogramType::from_cid(cid).unwrap_or_else(|| {
+        // Application Authorisation Referrals were dropped from EMV 4
+        notes.push(String::from("The card asks for a referral (AAR), which is handled as a decline"));
+        CryptogramType::AAC
+    });
+    if cid & 0x08 != 0 {
+        notes.push(String::from("The card asks for an advice to be sent to the issuer"));
+    }
+    match cid & 0x07 {
+        0x00 => {}
+        0x01 => notes.push(String::from("Reason: service not allowed")),
+        0x02 => notes.push(String::from("Reason: PIN Try Limit exceeded")),
+        0x03 => notes.push(String::from("Reason: issuer authentication failed")),
+        reason => notes.push(format!("Reason code {} is reserved", reason)),
+    }
+    Ok((returned, notes))
+}
+
+/// Whether the AIP flags the function as supported
+fn supports(session: &EmvSession, function: u8) -> bool {
+    session.card(0x82).and_then(|aip| aip.first()).is_some_and(|aip| aip & function != 0)
+}
+
+/// Amount in the minor unit of the currency, from its n12 value
+fn amount(session: &EmvSession, tag: u16) -> u64 {
+    session.terminal(tag).unwrap_or_default().iter()
+        .fold(0, |amount, byte| amount * 100 + u64::from(byte >> 4) * 10 + u64::from(byte & 0x0F))
+}
@@ -0,0 +1,385 @@
+//! Offline data authentication (EMV Book 2): the card's data is authenticated with the issuer public key, which a
+//! certification authority trusted by the terminal certifies
+use crate::apdu::capdu::CryptogramType;
+use crate::apdu::rapdu::RAPDU;
+use crate::connection::Transport;
+use crate::controller;
+use crate::error::EmvError;
+use crate::session::EmvSession;
+use crate::tlv::parser::{Tag, TLV};
+use crate::transaction::tvr::Tvr;
+use crate::utils::{crypto, system};
+
+/// Formats of the recovered data, in the byte following the header
+const ISSUER_PUBLIC_KEY_CERTIFICATE: u8 = 0x02;
+const SIGNED_STATIC_APPLICATION_DATA: u8 = 0x03;
+const ICC_PUBLIC_KEY_CERTIFICATE: u8 = 0x04;
+const SIGNED_DYNAMIC_APPLICATION_DATA: u8 = 0x05;
+
+/// SHA-1, the only hash algorithm and RSA, the only public key algorithm
+const HASH_ALGORITHM: u8 = 0x01;
+const PUBLIC_KEY_ALGORITHM: u8 = 0x01;
+
+/// Header, hash and trailer around the recovered data
+const HEADER: u8 = 0x6A;
+const TRAILER: u8 = 0xBC;
+const HASH_LENGTH: usize = 20;
+/// Shortest recovered data every field fits in, that of an ICC Public Key Certificate
+const MINIMUM_LENGTH: usize = 42;
+/// Leading byte of the PIN data enciphered for VERIFY
+const PIN_DATA_HEADER: u8 = 0x7F;
+
+/// RSA public key, as recovered from its certificate
+pub struct PublicKey {
+    modulus: Vec<u8>,
+    exponent: Vec<u8>,
+}
+
+/// Performs SDA over the static data, the records the AFL flags for offline data authentication along with the data
+/// objects of the Static Data Authentication Tag List (9F4A), setting the TVR bit when it fails
+pub fn sda(session: &mut EmvSession) -> Vec<String> {
+    let mut notes = Vec::new();
+    if [0x8F, 0x90, 0x9F32, 0x93].iter().any(|tag| session.card(*tag).is_none()) {
+        session.set_tvr(Tvr::IccDataMissing);
+    }
+    let result = static_data(session)
+        .and_then(|static_data| verify_static_data(session, &static_data, &mut notes));
+    if let Err(reason) = result {
+        notes.push(format!("SDA failed: {}", reason));
+        session.set_tvr(Tvr::StaticDataAuthenticationFailed);
+    }
+    notes
+}
+
+/// Performs DDA: the ICC public key, certified by the issuer over the static data, verifies the signature of the card
+/// over the DDOL data with INTERNAL AUTHENTICATE. The TVR bit is set when it fails.
+pub fn dda(card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+    let mut notes = Vec::new();
+    if [0x8F, 0x90, 0x9F32, 0x9F46, 0x9F47].iter().any(|tag| session.card(*tag).is_none()) {
+        session.set_tvr(Tvr::IccDataMissing);
+    }
+    let result = match icc_key(session, &mut notes) {
+        Ok(icc_key) => verify_dynamic_data(card, session, &icc_key, &mut notes)?,
+        Err(reason) => Err(reason),
+    };
+    if let Err(reason) = result {
+        notes.push(format!("DDA failed: {}", reason));
+        session.set_tvr(Tvr::DynamicDataAuthenticationFailed);
+    }
+    Ok(notes)
+}
+
+/// Recovers the ICC public key ahead of CDA, which the GENERATE AC commands only request once it's recovered. The TVR
+/// bit is set when it fails.
+pub fn cda_key(session: &mut EmvSession) -> (Option<PublicKey>, Vec<String>) {
+    let mut notes = Vec::new();
+    if [0x8F, 0x90, 0x9F32, 0x9F46, 0x9F47].iter().any(|tag| session.card(*tag).is_none()) {
+        session.set_tvr(Tvr::IccDataMissing);
+    }
+    match icc_key(session, &mut notes) {
+        Ok(icc_key) => (Some(icc_key), notes),
+        Err(reason) => {
+            notes.push(format!("CDA failed: {}", reason));
+            session.set_tvr(Tvr::CombinedDataAuthenticationFailed);
+            (None, notes)
+        }
+    }
+}
+
+/// Performs CDA over the response to a GENERATE AC that requested it: the Signed Dynamic Application Data (9F4B) holds
+/// the Application Cryptogram (9F26) along with a hash over the transaction data, the PDOL and CDOL data sent so far,
+/// and the other data objects of the response. The TVR bit is set when it fails.
+pub fn cda(session: &mut EmvSession, icc_key: &PublicKey, response: &RAPDU) -> Vec<String> {
+    let mut notes = Vec::new();
+    if let Err(reason) = verify_combined_data(session, icc_key, response, &mut notes) {
+        notes.push(format!("CDA failed: {}", reason));
+        session.set_tvr(Tvr::CombinedDataAuthenticationFailed);
+    }
+    notes
+}
+
+/// Recovers the key enciphering the offline PIN: the ICC PIN Encipherment public key when the card has its certificate
+/// (9F2D), the ICC public key otherwise
+pub fn pin_key(session: &EmvSession, notes: &mut Vec<String>) -> Result<PublicKey, String> {
+    let key = match session.card(0x9F2D) {
+        Some(certificate) => pin_encipherment_key(session, certificate, notes)?,
+        None => icc_key(session, notes)?,
+    };
+    if key.modulus.len() < MINIMUM_LENGTH {
+        return Err(format!("the {} bit key is too short to encipher the PIN", key.modulus.len() * 8));
+    }
+    Ok(key)
+}
+
+/// Enciphers the PIN block for VERIFY (EMV Book 2 §7.2): 7F, the PIN block and the ICC Unpredictable Number returned by
+/// GET CHALLENGE, padded with random bytes to the length of the key
+pub fn encipher_pin(key: &PublicKey, pin_block: &[u8], challenge: &[u8]) -> Vec<u8> {
+    let mut data = [&[PIN_DATA_HEADER][..], pin_block, challenge].concat();
+    data.extend(system::random(key.modulus.len().saturating_sub(data.len())));
+    crypto::rsa(&data, &key.modulus, &key.exponent)
+}
+
+fn verify_combined_data(session: &mut EmvSession, icc_key: &PublicKey, response: &RAPDU, notes: &mut Vec<String>) -> Result<(), String> {
+    let cid = *required(session, 0x9F27)?.first().ok_or("the Cryptogram Information Data is empty")?;
+    if CryptogramType::from_cid(cid) == Some(CryptogramType::AAC) {
+        notes.push(String::from("The card returned an AAC, which CDA doesn't sign"));
+        return Ok(());
+    }
+    let response_data = response_data(response).ok_or("the GENERATE AC response isn't coded in format 2 (77)")?;
+    let signed = response.find(&Tag::SignedDynamicApplicationData).map(|tlv| tlv.value().to_vec())
+        .ok_or("the GENERATE AC response has no Signed Dynamic Application Data")?;
+    session.store(0x9F4B, signed.clone());
+
+    let unpredictable_number = session.terminal(0x9F37).unwrap_or_default();
+    let dynamic_data = recover_dynamic_data(&signed, icc_key, unpredictable_number)?;
+    let dynamic_number = icc_dynamic_number(&dynamic_data)?;
+    notes.push(format!("Signed Dynamic Application Data verified over the Unpredictable Number {}, ICC Dynamic Number {}",
+                       hex::encode_upper(unpredictable_number), hex::encode_upper(&dynamic_number)));
+
+    // The ICC Dynamic Number is followed by the CID, the Application Cryptogram and the Transaction Data Hash Code
+    let data = &dynamic_data[1 + dynamic_number.len()..];
+    if data.len() < 1 + 8 + HASH_LENGTH {
+        return Err(String::from("the ICC dynamic data is too short to hold the CID, cryptogram and Transaction Data Hash Code"));
+    }
+    if data[0] != cid {
+        return Err(format!("the CID {:02X} of the Signed Dynamic Application Data isn't the {:02X} of the response", data[0], cid));
+    }
+    let transaction_data = session.transaction_data();
+    let hash = crypto::sha1(&[transaction_data, &response_data].concat());
+    if hash[..] != data[9..9 + HASH_LENGTH] {
+        return Err(format!("the Transaction Data Hash Code doesn't match the {} bytes of transaction data and {} bytes of response data",
+                           transaction_data.len(), response_data.len()));
+    }
+    notes.push(format!("Transaction Data Hash Code verified, Application Cryptogram {}", hex::encode_upper(&data[1..9])));
+    session.store(0x9F4C, dynamic_number);
+    session.store(0x9F26, data[1..9].to_vec());
+    Ok(())
+}
+
+/// Data objects of a format 2 response (77) as the card encoded them, but for the Signed Dynamic Application Data
+fn response_data(response: &RAPDU) -> Option<Vec<u8>> {
+    let (template, _) = TLV::parse(response.raw.clone()).ok().filter(|(tlv, _)| tlv.tag_number() == 0x77)?;
+    let mut data = template.value().to_vec();
+    let mut result = Vec::new();
+    while !data.is_empty() {
+        let (tlv, remainder) = TLV::parse(data.clone()).ok()?;
+        if tlv.tag_number() != 0x9F4B {
+            result.extend(&data[..data.len() - remainder.len()]);
+        }
+        data = remainder;
+    }
+    Some(result)
+}
+
+/// Has the card sign the DDOL data, only failing on transport errors, while a failed check is the inner error
+fn verify_dynamic_data(card: &dyn Transport, session: &mut EmvSession, icc_key: &PublicKey, notes: &mut Vec<String>) -> Result<Result<(), String>, EmvError> {
+    let ddol_data = match session.ddol_data() {
+        Some(ddol_data) => ddol_data,
+        None => return Ok(Err(String::from("the DDOL is malformed"))),
+    };
+    let signed = match controller::internal_authenticate(card, ddol_data.clone()) {
+        Ok(signed) => signed,
+        Err(EmvError::Status(status)) => return Ok(Err(format!("the card rejected INTERNAL AUTHENTICATE: {}", status))),
+        Err(EmvError::Parse(err)) => return Ok(Err(format!("the INTERNAL AUTHENTICATE response is malformed: {}", err))),
+        Err(err) => return Err(err),
+    };
+    session.store(0x9F4B, signed.clone());
+
+    let result = recover_dynamic_data(&signed, icc_key, &ddol_data).and_then(|dynamic_data| {
+        let dynamic_number = icc_dynamic_number(&dynamic_data)?;
+        notes.push(format!("Signed Dynamic Application Data verified over the DDOL data {}, ICC Dynamic Number {}",
+                           hex::encode_upper(&ddol_data), hex::encode_upper(&dynamic_number)));
+        session.store(0x9F4C, dynamic_number);
+        Ok(())
+    });
+    Ok(result)
+}
+
+fn verify_static_data(session: &mut EmvSession, static_data: &[u8], notes: &mut Vec<String>) -> Result<(), String> {
+    let signed = required(session, 0x93)?.to_vec();
+    let issuer_key = issuer_key(session, notes)?;
+
+    let recovered = recover(&signed, &issuer_key, SIGNED_STATIC_APPLICATION_DATA, "Signed Static Application Data")?;
+    if recovered[2] != HASH_ALGORITHM {
+        return Err(format!("the Signed Static Application Data uses the unknown hash algorithm {:02X}", recovered[2]));
+    }
+    check_hash(&recovered, static_data, "Signed Static Application Data")?;
+
+    let data_authentication_code = recovered[3..5].to_vec();
+    notes.push(format!("Signed Static Application Data verified over {} bytes of static data, Data Authentication Code {}",
+                       static_data.len(), hex::encode_upper(&data_authentication_code)));
+    session.store(0x9F45, data_authentication_code);
+    Ok(())
+}
+
+/// Recovers the issuer public key from its certificate (90), the remainder (92) and exponent (9F32), with the CA public
+/// key the card names (8F)
+fn issuer_key(session: &EmvSession, notes: &mut Vec<String>) -> Result<PublicKey, String> {
+    let index = *required(session, 0x8F)?.first().ok_or("the CA Public Key Index is empty")?;
+    let certificate = required(session, 0x90)?;
+    let exponent = required(session, 0x9F32)?.to_vec();
+    let remainder = session.card(0x92).unwrap_or_default();
+
+    let aid = session.application().unwrap_or_default();
+    let ca_key = session.profile().ca_key(aid, index)
+        .ok_or_else(|| format!("the terminal has no CA public key {} of the RID {}", hex::encode_upper([index]), hex::encode_upper(&aid[..aid.len().min(5)])))?;
+    notes.push(format!("CA public key {} of the RID {}, {} bits", hex::encode_upper([index]), hex::encode_upper(ca_key.rid), ca_key.modulus.len() * 8));
+    let ca_key = PublicKey { modulus: ca_key.modulus.clone(), exponent: ca_key.exponent.clone() };
+
+    let recovered = recover(certificate, &ca_key, ISSUER_PUBLIC_KEY_CERTIFICATE, "Issuer Public Key Certificate")?;
+    check_hash(&recovered, &[remainder, &exponent].concat(), "Issuer Public Key Certificate")?;
+    let issuer_identifier = hex::encode_upper(&recovered[2..6]).trim_end_matches('F').to_string();
+    let pan = hex::encode_upper(session.card(0x5A).unwrap_or_default());
+    if issuer_identifier.len() < 3 || !pan.starts_with(&issuer_identifier) {
+        return Err(format!("the issuer identifier {} doesn't match the PAN", issuer_identifier));
+    }
+    check_expiration(session, &recovered[6..8], "Issuer Public Key Certificate")?;
+    if recovered[11] != HASH_ALGORITHM || recovered[12] != PUBLIC_KEY_ALGORITHM {
+        return Err(format!("the Issuer Public Key Certificate uses the unknown algorithms {:02X} and {:02X}", recovered[11], recovered[12]));
+    }
+
+    let modulus = public_key_modulus(&recovered[15..recovered.len() - HASH_LENGTH - 1], recovered[13], remainder)
+        .ok_or("the issuer public key doesn't match its length, the Issuer Public Key Remainder is missing")?;
+    notes.push(format!("Issuer Public Key Certificate recovered: issuer {}, serial {}, valid through {}/{}, {} bit key",
+                       issuer_identifier, hex::encode_upper(&recovered[8..11]), hex::encode_upper(&recovered[6..7]),
+                       hex::encode_upper(&recovered[7..8]), modulus.len() * 8));
+    Ok(PublicKey { modulus, exponent })
+}
+
+/// Recovers the ICC public key from its certificate (9F46), the remainder (9F48) and exponent (9F47), with the issuer
+/// public key. The certificate's hash also covers the static data.
+fn icc_key(session: &EmvSession, notes: &mut Vec<String>) -> Result<PublicKey, String> {
+    let static_data = static_data(session)?;
+    let issuer_key = issuer_key(session, notes)?;
+    let certificate = required(session, 0x9F46)?;
+    let exponent = required(session, 0x9F47)?.to_vec();
+    let remainder = session.card(0x9F48).unwrap_or_default();
+    let modulus = certified_key(session, &issuer_key, certificate, remainder, &[remainder, &exponent, &static_data].concat(), "ICC", notes)?;
+    Ok(PublicKey { modulus, exponent })
+}
+
+/// Recovers the ICC PIN Encipherment public key from its certificate (9F2D), the remainder (9F2F) and exponent (9F2E),
+/// with the issuer public key
+fn pin_encipherment_key(session: &EmvSession, certificate: &[u8], notes: &mut Vec<String>) -> Result<PublicKey, String> {
+    let issuer_key = issuer_key(session, notes)?;
+    let exponent = required(session, 0x9F2E)?.to_vec();
+    let remainder = session.card(0x9F2F).unwrap_or_default();
+    let modulus = certified_key(session, &issuer_key, certificate, remainder, &[remainder, &exponent].concat(), "ICC PIN Encipherment", notes)?;
+    Ok(PublicKey { modulus, exponent })
+}
+
+/// Recovers the modulus of a key the issuer certifies for the card, the certificate's hash covering the data given
+fn certified_key(session: &EmvSession, issuer_key: &PublicKey, certificate: &[u8], remainder: &[u8], data: &[u8], key: &str, notes: &mut Vec<String>) -> Result<Vec<u8>, String> {
+    let name = format!("{} Public Key Certificate", key);
+    let recovered = recover(certificate, issuer_key, ICC_PUBLIC_KEY_CERTIFICATE, &name)?;
+    check_hash(&recovered, data, &name)?;
+    let pan = hex::encode_upper(&recovered[2..12]).trim_end_matches('F').to_string();
+    if pan != hex::encode_upper(session.card(0x5A).unwrap_or_default()).trim_end_matches('F') {
+        return Err(format!("the PAN {} of the {} isn't the card's", pan, name));
+    }
+    check_expiration(session, &recovered[12..14], &name)?;
+    if recovered[17] != HASH_ALGORITHM || recovered[18] != PUBLIC_KEY_ALGORITHM {
+        return Err(format!("the {} uses the unknown algorithms {:02X} and {:02X}", name, recovered[17], recovered[18]));
+    }
+
+    let modulus = public_key_modulus(&recovered[21..recovered.len() - HASH_LENGTH - 1], recovered[19], remainder)
+        .ok_or_else(|| format!("the {0} public key doesn't match its length, the {0} Public Key Remainder is missing", key))?;
+    notes.push(format!("{} recovered: serial {}, valid through {}/{}, {} bit key",
+                       name, hex::encode_upper(&recovered[14..17]), hex::encode_upper(&recovered[12..13]),
+                       hex::encode_upper(&recovered[13..14]), modulus.len() * 8));
+    Ok(modulus)
+}
+
+/// Recovers the Signed Dynamic Application Data with the ICC public key, returning the ICC dynamic data it holds once
+/// the hash over it and the terminal data checks
+fn recover_dynamic_data(signed: &[u8], icc_key: &PublicKey, terminal_data: &[u8]) -> Result<Vec<u8>, String> {
+    let recovered = recover(signed, icc_key, SIGNED_DYNAMIC_APPLICATION_DATA, "Signed Dynamic Application Data")?;
+    if recovered[2] != HASH_ALGORITHM {
+        return Err(format!("the Signed Dynamic Application Data uses the unknown hash algorithm {:02X}", recovered[2]));
+    }
+    let length = usize::from(recovered[3]);
+    if 4 + length > recovered.len() - HASH_LENGTH - 1 {
+        return Err(String::from("the ICC dynamic data overflows the Signed Dynamic Application Data"));
+    }
+    check_hash(&recovered, terminal_data, "Signed Dynamic Application Data")?;
+    Ok(recovered[4..4 + length].to_vec())
+}
+
+/// ICC Dynamic Number (9F4C) leading the ICC dynamic data, after its length
+fn icc_dynamic_number(dynamic_data: &[u8]) -> Result<Vec<u8>, String> {
+    match dynamic_data.split_first() {
+        Some((length, data)) if (2..=8).contains(length) && usize::from(*length) <= data.len() => Ok(data[..usize::from(*length)].to_vec()),
+        _ => Err(String::from("the ICC Dynamic Number is malformed")),
+    }
+}
+
+/// Concatenation of the static data, failing when a record isn't coded as a record template (70) or the tag list names
+/// data objects other than the AIP (82)
+fn static_data(session: &EmvSession) -> Result<Vec<u8>, String> {
+    let mut data = session.authenticated_records().ok_or("a record for offline data authentication isn't coded as a record template")?;
+    match session.card(0x9F4A) {
+        Some([0x82]) => data.extend(session.card(0x82).unwrap_or_default()),
+        Some(tag_list) => return Err(format!("the Static Data Authentication Tag List {} names other data than the AIP", hex::encode_upper(tag_list))),
+        None => {}
+    }
+    Ok(data)
+}
+
+/// Recovers the data signed with the private key, checking its length, header, format and trailer
+fn recover(signed: &[u8], key: &PublicKey, format: u8, name: &str) -> Result<Vec<u8>, String> {
+    if signed.len() != key.modulus.len() {
+        return Err(format!("the {} is {} bytes long instead of the {} of the key", name, signed.len(), key.modulus.len()));
+    }
+    if key.modulus.len() < MINIMUM_LENGTH {
+        return Err(format!("the key is too short to recover the {}", name));
+    }
+    let recovered = crypto::rsa(signed, &key.modulus, &key.exponent);
+    if recovered[recovered.len() - 1] != TRAILER {
+        return Err(format!("the {} recovered with a wrong trailer", name));
+    }
+    if recovered[0] != HEADER {
+        return Err(format!("the {} recovered with a wrong header", name));
+    }
+    if recovered[1] != format {
+        return Err(format!("the {} recovered with the format {:02X} instead of {:02X}", name, recovered[1], format));
+    }
+    Ok(recovered)
+}
+
+/// Compares the hash in the recovered data with the one over the data between the header and the hash, followed by the
+/// data given
+fn check_hash(recovered: &[u8], data: &[u8], name: &str) -> Result<(), String> {
+    let end = recovered.len() - HASH_LENGTH - 1;
+    let hash = crypto::sha1(&[&recovered[1..end], data].concat());
+    if hash[..] != recovered[end..end + HASH_LENGTH] {
+        return Err(format!("the hash of the {} doesn't match", name));
+    }
+    Ok(())
+}
+
+/// Certificates expire at the end of the month of their MMYY expiration date
+fn check_expiration(session: &EmvSession, expiration: &[u8], name: &str) -> Result<(), String> {
+    let today = session.terminal(0x9A).unwrap_or_default();
+    if today.len() == 3 && (year(expiration[1]), expiration[0]) < (year(today[0]), today[1]) {
+        return Err(format!("the {} expired on {}/{}", name, hex::encode_upper(&expiration[..1]), hex::encode_upper(&expiration[1..])));
+    }
+    Ok(())
+}
+
+/// Full year of a BCD YY, from 1950 to 2049 as in EMV Book 2
+fn year(yy: u8) -> u16 {
+    let yy = u16::from(yy >> 4) * 10 + u16::from(yy & 0x0F);
+    if yy < 50 { 2000 + yy } else { 1900 + yy }
+}
+
+/// Public key modulus from the leftmost digits in the certificate, padded with BB, and the remainder when it doesn't
+/// fit in the certificate
+fn public_key_modulus(digits: &[u8], length: u8, remainder: &[u8]) -> Option<Vec<u8>> {
+    let length = usize::from(length);
+    let modulus = if length <= digits.len() { digits[..length].to_vec() } else { [digits, remainder].concat() };
+    Some(modulus).filter(|modulus| modulus.len() == length)
+}
+
+fn required(session: &EmvSession, tag: u16) -> Result<&[u8], String> {
+    session.card(tag).ok_or_else(|| format!("the card didn't provide {:02X}", tag))
+}
@@ -0,0 +1,200 @@
+//! Online processing and completion (EMV Book 3 §10.9 to §10.11): the issuer's authorisation response is applied to
+//! the card through issuer authentication, the issuer scripts and the second GENERATE AC
+use crate::apdu::capdu::{APDU, CryptogramType};
+use crate::apdu::rapdu::Category;
+use crate::connection::Transport;
+use crate::controller;
+use crate::error::EmvError;
+use crate::session::EmvSession;
+use crate::tlv::dol;
+use crate::tlv::parser::TLV;
+use crate::transaction::{AIP_ISSUER_AUTHENTICATION, Interaction, Transaction, action, cryptogram, supports};
+use crate::transaction::tvr::{Tsi, Tvr};
+
+/// Authorisation Response Codes (8A) the terminal sets itself when the issuer can't be reached
+const OFFLINE_APPROVED: &[u8; 2] = b"Y3";
+const OFFLINE_DECLINED: &[u8; 2] = b"Z3";
+
+/// Script result nibbles of the Issuer Script Results (9F5B)
+const SCRIPT_FAILED: u8 = 0x10;
+const SCRIPT_SUCCESSFUL: u8 = 0x20;
+
+/// Answer of the issuer to the authorisation request
+#[derive(Debug, Clone)]
+pub struct AuthorisationResponse {
+    /// Authorisation Response Code (8A), two alphanumeric characters
+    pub response_code: [u8; 2],
+    /// Issuer Authentication Data (91), which the card checks to authenticate the issuer
+    pub issuer_authentication_data: Option<Vec<u8>>,
+    /// Issuer Script Templates 1 (71) and 2 (72), as received
+    pub scripts: Vec<u8>,
+}
+
+impl AuthorisationResponse {
+    /// Stub of a host approving every transaction, which can't authenticate itself without the issuer keys
+    pub fn approval() -> AuthorisationResponse {
+        AuthorisationResponse { response_code: *b"00", issuer_authentication_data: None, scripts: vec![] }
+    }
+
+    /// Parses the data objects of the response, as sent by the host, other data objects being ignored
+    pub fn parse(data: &[u8]) -> Result<AuthorisationResponse, &'static str> {
+        let mut response_code = None;
+        let mut issuer_authentication_data = None;
+        let mut scripts = Vec::new();
+        let mut data = data.to_vec();
+        while !data.is_empty() {
+            let (tlv, remainder) = TLV::parse(data)?;
+            match tlv.tag_number() {
+                0x8A if tlv.value().len() == 2 => response_code = Some([tlv.value()[0], tlv.value()[1]]),
+                0x8A => return Err("The Authorisation Response Code must be 2 bytes long!"),
+                0x91 if (8..=16).contains(&tlv.value().len()) => issuer_authentication_data = Some(tlv.value().to_vec()),
+                0x91 => return Err("The Issuer Authentication Data must be 8 to 16 bytes long!"),
+                tag @ 0x71 | tag @ 0x72 => scripts.extend(TLV::encode(tag, tlv.value())),
+                _ => {}
+            }
+            data = remainder;
+        }
+        let response_code = response_code.ok_or("The Authorisation Response Code (8A) is missing!")?;
+        Ok(AuthorisationResponse { response_code, issuer_authentication_data, scripts })
+    }
+}
+
+/// Whether the Authorisation Response Code approves the transaction, as the issuer or the terminal standing in for it
+pub fn approved(response_code: &[u8]) -> bool {
+    matches!(response_code, b"00" | b"08" | b"10" | b"11" | b"Y1" | b"Y3")
+}
+
+/// Cryptogram the second GENERATE AC requests, following the Authorisation Response Code (8A) once it's known
+pub fn second_cryptogram(session: &EmvSession) -> Option<CryptogramType> {
+    let response_code = session.terminal(0x8A)?;
+    Some(if approved(response_code) { CryptogramType::TC } else { CryptogramType::AAC })
+}
+
+/// Sends the authorisation request, then has the card authenticate the issuer with the Issuer Authentication Data (91)
+/// of the response. Without a response, the terminal decides with the default action codes.
+pub fn authorise(card: &dyn Transport, session: &mut EmvSession, interaction: &dyn Interaction) -> Result<(Option<AuthorisationResponse>, Vec<String>), EmvError> {
+    let response = match interaction.authorise(session) {
+        Some(response) => response,
+        None => {
+            let (cryptogram_type, mut notes) = action::default_action(session);
+            let response_code = if cryptogram_type == CryptogramType::TC { OFFLINE_APPROVED } else { OFFLINE_DECLINED };
+            session.set_terminal(0x8A, response_code.to_vec());
+            notes.insert(0, format!("The issuer can't be reached, Authorisation Response Code {}", String::from_utf8_lossy(response_code)));
+            return Ok((None, notes));
+        }
+    };
+
+    session.set_terminal(0x8A, response.response_code.to_vec());
+    let outcome = match &response.response_code {
+        code if approved(code) => "approved",
+        b"01" | b"02" => "referred, which is handled as a decline",
+        _ => "declined",
+    };
+    let mut notes = vec![format!("Authorisation Response Code {}: {} by the issuer", String::from_utf8_lossy(&response.response_code), outcome)];
+
+    if let Some(issuer_authentication_data) = &response.issuer_authentication_data {
+        session.set_terminal(0x91, issuer_authentication_data.clone());
+        if supports(session, AIP_ISSUER_AUTHENTICATION) {
+            session.set_tsi(Tsi::IssuerAuthenticationPerformed);
+            match controller::external_authenticate(card, issuer_authentication_data.clone()) {
+                Ok(_) => notes.push(String::from("The card authenticated the issuer")),
+                Err(EmvError::Status(status)) => {
+                    session.set_tvr(Tvr::IssuerAuthenticationFailed);
+                    notes.push(format!("The card failed to authenticate the issuer: {}", status));
+                }
+                Err(err) => return Err(err),
+            }
+        } else if requested_by_cdol2(session, 0x91) {
+            notes.push(String::from("The Issuer Authentication Data is passed to the card in the CDOL2 data"));
+        } else {
+            notes.push(String::from("The card doesn't support issuer authentication, the Issuer Authentication Data is ignored"));
+        }
+    }
+    Ok((Some(response), notes))
+}
+
+/// Runs the issuer scripts around the second GENERATE AC, which requests a TC when the transaction was approved and an
+/// AAC otherwise. The TC is declined when CDA fails.
+pub fn complete(transaction: &mut Transaction, card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+    let scripts = transaction.authorisation.as_ref().map_or(vec![], |response| response.scripts.clone());
+    let mut notes = Vec::new();
+    let mut results = Vec::new();
+    run_scripts(card, session, &scripts, 0x71, &mut notes, &mut results)?;
+
+    let cryptogram_type = second_cryptogram(session).unwrap_or(CryptogramType::AAC);
+    let cda_notes = transaction.generate_ac(card, session, cryptogram_type, false)?;
+    let (returned, cryptogram_notes) = cryptogram(session)?;
+    if returned == CryptogramType::ARQC || (cryptogram_type == CryptogramType::AAC && returned == CryptogramType::TC) {
+        return Err(EmvError::Terminated("The card returned a cryptogram the second GENERATE AC can't return"));
+    }
+    notes.push(format!("Requested {:?}{}, the card returned {:?} {}", cryptogram_type, transaction.cda_note(cryptogram_type), returned,
+                       hex::encode_upper(session.card(0x9F26).unwrap_or_default())));
+    notes.extend(cryptogram_notes);
+    notes.extend(cda_notes);
+    // A TC whose signature doesn't verify can't be trusted
+    let approved = returned == CryptogramType::TC && !transaction.cda_failed(session);
+
+    run_scripts(card, session, &scripts, 0x72, &mut notes, &mut results)?;
+    if !results.is_empty() {
+        session.set_terminal(0x9F5B, results);
+    }
+    notes.push(String::from(if approved { "Transaction approved" } else { "Transaction declined" }));
+    Ok(notes)
+}
+
+/// Sends the commands (86) of the scripts in the templates given, a script stopping at the first command the card
+/// rejects. The result of each script is appended to the Issuer Script Results.
+fn run_scripts(card: &dyn Transport, session: &mut EmvSession, scripts: &[u8], template: u16, notes: &mut Vec<String>, results: &mut Vec<u8>) -> Result<(), EmvError> {
+    for script in templates(scripts).into_iter().filter(|script| script.tag_number() == template) {
+        let children = templates(script.value());
+        let identifier = children.iter().find(|tlv| tlv.tag_number() == 0x9F18).map_or(vec![0x00; 4], |tlv| tlv.value().to_vec());
+        let commands: Vec<&TLV> = children.iter().filter(|tlv| tlv.tag_number() == 0x86).collect();
+
+        let mut failed = None;
+        for (sequence, command) in commands.iter().enumerate() {
+            let accepted = match APDU::parse(command.value()) {
+                Ok(apdu) => match controller::send_apdu(card, apdu) {
+                    Ok(_) => true,
+                    Err(EmvError::Status(status)) => status.category() == Category::Warning,
+                    Err(err) => return Err(err),
+                },
+                Err(_) => false,
+            };
+            if !accepted {
+                failed = Some(sequence + 1);
+                break;
+            }
+        }
+
+        session.set_tsi(Tsi::ScriptProcessingPerformed);
+        let name = format!("Script {} ({:02X})", hex::encode_upper(&identifier), template);
+        match failed {
+            Some(sequence) => {
+                session.set_tvr(if template == 0x71 { Tvr::ScriptFailedBeforeFinalGenerateAc } else { Tvr::ScriptFailedAfterFinalGenerateAc });
+                notes.push(format!("{} failed at command {} of {}", name, sequence, commands.len()));
+                results.push(SCRIPT_FAILED | sequence.min(0x0F) as u8);
+            }
+            None => {
+                notes.push(format!("{} completed, {} command{}", name, commands.len(), if commands.len() == 1 { "" } else { "s" }));
+                results.push(SCRIPT_SUCCESSFUL);
+            }
+        }
+        results.extend(identifier);
+    }
+    Ok(())
+}
+
+/// Data objects at the top level, templates being kept whole
+fn templates(data: &[u8]) -> Vec<TLV> {
+    let mut result = Vec::new();
+    let mut data = data.to_vec();
+    while let Ok((tlv, remainder)) = TLV::parse(data) {
+        result.push(tlv);
+        data = remainder;
+    }
+    result
+}
+
+fn requested_by_cdol2(session: &EmvSession, tag: u16) -> bool {
+    session.card(0x8D).and_then(|cdol| dol::parse(cdol).ok()).is_some_and(|cdol| cdol.iter().any(|entry| entry.tag == tag))
+}
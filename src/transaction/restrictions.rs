@@ -0,0 +1,133 @@
+//! Processing restrictions (EMV Book 3 §10.4): whether the application may be used for this transaction, as told by
+//! its version, usage control and dates
+use crate::session::EmvSession;
+use crate::transaction::tvr::Tvr;
+
+/// Application Usage Control (9F07) bits, the first byte in the high half
+const AUC_DOMESTIC_CASH: u16 = 0x8000;
+const AUC_INTERNATIONAL_CASH: u16 = 0x4000;
+const AUC_DOMESTIC_GOODS: u16 = 0x2000;
+const AUC_INTERNATIONAL_GOODS: u16 = 0x1000;
+const AUC_DOMESTIC_SERVICES: u16 = 0x0800;
+const AUC_INTERNATIONAL_SERVICES: u16 = 0x0400;
+const AUC_ATMS: u16 = 0x0200;
+const AUC_OTHER_TERMINALS: u16 = 0x0100;
+const AUC_DOMESTIC_CASHBACK: u16 = 0x0080;
+const AUC_INTERNATIONAL_CASHBACK: u16 = 0x0040;
+
+/// Transaction types (9C) the usage control tells apart
+const CASH: u8 = 0x01;
+const CASHBACK: u8 = 0x09;
+
+/// Checks the application version, usage control and dates against the transaction, setting the TVR bits of the
+/// restrictions that apply
+pub fn check(session: &mut EmvSession) -> Vec<String> {
+    let mut notes = Vec::new();
+    check_version(session, &mut notes);
+    check_usage_control(session, &mut notes);
+    check_dates(session, &mut notes);
+    notes
+}
+
+/// The card's application version (9F08) is expected to be the one the terminal implements (9F09)
+fn check_version(session: &mut EmvSession, notes: &mut Vec<String>) {
+    let (card, terminal) = match (session.card(0x9F08), session.terminal(0x9F09)) {
+        (Some(card), Some(terminal)) => (card.to_vec(), terminal.to_vec()),
+        (None, _) => return notes.push(String::from("The card has no application version, assumed compatible")),
+        (_, None) => return notes.push(String::from("The terminal has no application version for the AID, assumed compatible")),
+    };
+    if card == terminal {
+        notes.push(format!("Application version {} matches the terminal's", hex::encode_upper(card)));
+    } else {
+        notes.push(format!("Application version {} differs from the terminal's {}", hex::encode_upper(card), hex::encode_upper(terminal)));
+        session.set_tvr(Tvr::DifferentApplicationVersions);
+    }
+}
+
+/// The AUC (9F07) must allow the kind of terminal, and when the issuer country (5F28) is known, the transaction type
+/// in the domestic or international setting
+fn check_usage_control(session: &mut EmvSession, notes: &mut Vec<String>) {
+    let auc = match session.card(0x9F07) {
+        Some([first, second]) => u16::from_be_bytes([*first, *second]),
+        Some(_) => return notes.push(String::from("The Application Usage Control is malformed, ignored")),
+        None => return notes.push(String::from("The card has no Application Usage Control")),
+    };
+    let terminal_type = session.terminal(0x9F35).and_then(|value| value.first()).copied().unwrap_or_default();
+    let additional_capabilities = session.terminal(0x9F40).and_then(|value| value.first()).copied().unwrap_or_default();
+    // ATMs are unattended terminals that dispense cash
+    let atm = matches!(terminal_type, 0x14..=0x16) && additional_capabilities & 0x80 != 0;
+
+    let mut allowed = if atm { auc & AUC_ATMS != 0 } else { auc & AUC_OTHER_TERMINALS != 0 };
+    let mut setting = "";
+    if let (Some(issuer), Some(terminal)) = (session.card(0x5F28), session.terminal(0x9F1A)) {
+        let domestic = issuer == terminal;
+        let pick = |domestic_bit: u16, international_bit: u16| if domestic { domestic_bit } else { international_bit };
+        let transaction_type = session.terminal(0x9C).and_then(|value| value.first()).copied().unwrap_or_default();
+        let amount_other = session.terminal(0x9F03).is_some_and(|value| value.iter().any(|byte| *byte != 0));
+
+        allowed &= match transaction_type {
+            CASH => auc & pick(AUC_DOMESTIC_CASH, AUC_INTERNATIONAL_CASH) != 0,
+            // Purchases are of goods, services or both, as sold by the terminal
+            _ => {
+                let goods = additional_capabilities & 0x40 != 0 && auc & pick(AUC_DOMESTIC_GOODS, AUC_INTERNATIONAL_GOODS) != 0;
+                let services = additional_capabilities & 0x20 != 0 && auc & pick(AUC_DOMESTIC_SERVICES, AUC_INTERNATIONAL_SERVICES) != 0;
+                goods || services
+            }
+        };
+        if transaction_type == CASHBACK || amount_other {
+            allowed &= auc & pick(AUC_DOMESTIC_CASHBACK, AUC_INTERNATIONAL_CASHBACK) != 0;
+        }
+        setting = if domestic { " domestic" } else { " international" };
+    }
+
+    let terminal = if atm { "ATM" } else { "terminal" };
+    if allowed {
+        notes.push(format!("Application Usage Control {:04X} allows this{} transaction at this {}", auc, setting, terminal));
+    } else {
+        notes.push(format!("Application Usage Control {:04X} doesn't allow this{} transaction at this {}", auc, setting, terminal));
+        session.set_tvr(Tvr::ServiceNotAllowed);
+    }
+}
+
+/// The transaction date (9A) must be within the application effective (5F25) and expiration (5F24) dates
+fn check_dates(session: &mut EmvSession, notes: &mut Vec<String>) {
+    let today = match session.terminal(0x9A).and_then(date) {
+        Some(today) => today,
+        None => return notes.push(String::from("The transaction date is missing, the dates can't be checked")),
+    };
+    match session.card(0x5F25).map(date) {
+        Some(Some(effective)) if effective > today => {
+            notes.push(format!("The application is only effective from {}", format_date(effective)));
+            session.set_tvr(Tvr::ApplicationNotYetEffective);
+        }
+        Some(Some(effective)) => notes.push(format!("The application is effective since {}", format_date(effective))),
+        Some(None) => notes.push(String::from("The application effective date is malformed, ignored")),
+        None => {}
+    }
+    match session.card(0x5F24).map(date) {
+        Some(Some(expiration)) if expiration < today => {
+            notes.push(format!("The application expired on {}", format_date(expiration)));
+            session.set_tvr(Tvr::ExpiredApplication);
+        }
+        Some(Some(expiration)) => notes.push(format!("The application expires on {}", format_date(expiration))),
+        Some(None) => {
+            notes.push(String::from("The application expiration date is malformed"));
+            session.set_tvr(Tvr::ExpiredApplication);
+        }
+        None => notes.push(String::from("The card has no expiration date")),
+    }
+}
+
+/// Reads a n6 YYMMDD date as YYYYMMDD, years below 50 being in the 21st century (EMV Book 4 §6.7.3)
+fn date(value: &[u8]) -> Option<u32> {
+    if value.len() != 3 || value.iter().any(|byte| byte >> 4 > 9 || byte & 0x0F > 9) {
+        return None;
+    }
+    let date = value.iter().fold(0, |date, byte| date * 100 + u32::from(byte >> 4) * 10 + u32::from(byte & 0x0F));
+    let century = if date / 10000 < 50 { 20000000 } else { 19000000 };
+    Some(century + date)
+}
+
+fn format_date(date: u32) -> String {
+    format!("{:04}-{:02}-{:02}", date / 10000, date / 100 % 100, date % 100)
+}
@@ -0,0 +1,120 @@
+//! Terminal risk management (EMV Book 3 §10.6): floor limit, random transaction selection and velocity checking,
+//! protecting the acquirer, issuer and system from fraud on transactions that could be approved offline
+use crate::connection::Transport;
+use crate::controller;
+use crate::error::EmvError;
+use crate::session::EmvSession;
+use crate::transaction::amount;
+use crate::transaction::tvr::{Tsi, Tvr};
+use crate::utils::system;
+
+/// Performs the checks, recording the counters read from the card along with the TVR and TSI bits
+pub fn manage(card: &dyn Transport, session: &mut EmvSession) -> Result<Vec<String>, EmvError> {
+    let mut notes = Vec::new();
+    let amount = amount(session, 0x9F02);
+    let floor_limit = session.terminal(0x9F1B)
+        .filter(|value| value.len() == 4)
+        .map_or(0, |value| u64::from(u32::from_be_bytes([value[0], value[1], value[2], value[3]])));
+
+    check_floor_limit(session, amount, floor_limit, &mut notes);
+    if amount < floor_limit {
+        select_randomly(session, amount, floor_limit, &mut notes);
+    }
+    check_velocity(card, session, &mut notes)?;
+    session.set_tsi(Tsi::TerminalRiskManagementPerformed);
+    Ok(notes)
+}
+
+fn check_floor_limit(session: &mut EmvSession, amount: u64, floor_limit: u64, notes: &mut Vec<String>) {
+    if amount >= floor_limit {
+        notes.push(format!("Amount {} reaches the floor limit {}", amount, floor_limit));
+        session.set_tvr(Tvr::FloorLimitExceeded);
+    } else {
+        notes.push(format!("Amount {} is below the floor limit {}", amount, floor_limit));
+    }
+}
+
+/// Selects transactions below the floor limit for online processing at random, the probability growing from the
+/// target percentage at the threshold up to the maximum target percentage at the floor limit
+fn select_randomly(session: &mut EmvSession, amount: u64, floor_limit: u64, notes: &mut Vec<String>) {
+    let parameters = session.profile().random_selection.clone();
+    let target = u64::from(parameters.target_percentage);
+    let percentage = if amount < parameters.threshold {
+        target
+    } else {
+        let maximum = u64::from(parameters.max_target_percentage).max(target);
+        let range = floor_limit.saturating_sub(parameters.threshold).max(1);
+        target + (maximum - target) * (amount - parameters.threshold) / range
+    };
+    if percentage == 0 {
+        return;
+    }
+
+    // Bytes from 198 up are drawn again, leaving each number from 1 to 99 two bytes
+    let number = loop {
+        let byte = system::random(1)[0];
+        if byte < 198 {
+            break u64::from(byte) % 99 + 1;
+        }
+    };
+    if number <= percentage {
+        notes.push(format!("Selected for online processing at random ({} within {}%)", number, percentage));
+        session.set_tvr(Tvr::RandomlySelectedForOnline);
+    } else {
+        notes.push(format!("Not selected for online processing at random ({} beyond {}%)", number, percentage));
+    }
+}
+
+/// Compares the offline transactions since the last online one, told by the ATC (9F36) and Last Online ATC Register
+/// (9F13), with the Lower and Upper Consecutive Offline Limits (9F14 and 9F23) set by the issuer
+fn check_velocity(card: &dyn Transport, session: &mut EmvSession, notes: &mut Vec<String>) -> Result<(), EmvError> {
+    let (lower, upper) = match (session.card(0x9F14), session.card(0x9F23)) {
+        (Some([lower]), Some([upper])) => (u16::from(*lower), u16::from(*upper)),
+        _ => {
+            notes.push(String::from("The card has no consecutive offline limits, velocity checking skipped"));
+            return Ok(());
+        }
+    };
+
+    let counters = (counter(card, session, 0x9F36)?, counter(card, session, 0x9F13)?);
+    let (atc, last_online_atc) = match counters {
+        (Some(atc), Some(last_online_atc)) => (atc, last_online_atc),
+        _ => {
+            notes.push(String::from("The card doesn't disclose its counters, both offline limits are taken as exceeded"));
+            session.set_tvr(Tvr::LowerConsecutiveOfflineLimitExceeded);
+            session.set_tvr(Tvr::UpperConsecutiveOfflineLimitExceeded);
+            return Ok(());
+        }
+    };
+
+    let offline = atc.saturating_sub(last_online_atc);
+    notes.push(format!("{} transactions since the last online one (ATC {}, last online ATC {}), limits {} and {}",
+                       offline, atc, last_online_atc, lower, upper));
+    if offline > lower {
+        session.set_tvr(Tvr::LowerConsecutiveOfflineLimitExceeded);
+    }
+    if offline > upper {
+        session.set_tvr(Tvr::UpperConsecutiveOfflineLimitExceeded);
+    }
+    // Cards that never went online are new
+    if last_online_atc == 0 {
+        session.set_tvr(Tvr::NewCard);
+    }
+    Ok(())
+}
+
+/// Reads a two byte counter with GET DATA, none when the card doesn't disclose it
+fn counter(card: &dyn Transport, session: &mut EmvSession, tag: u16) -> Result<Option<u16>, EmvError> {
+    match controller::get_data(card, tag) {
+        Ok(value) => {
+            let counter = match value.as_slice() {
+                [msb, lsb] => Some(u16::from_be_bytes([*msb, *lsb])),
+                _ => None,
+            };
+            session.store(tag, value);
+            Ok(counter)
+        }
+        Err(EmvError::Status(_)) | Err(EmvError::Parse(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+
+/// Bits of the Terminal Verification Results (95), recording the outcome of the checks made during the transaction
+/// (EMV Book 3 Annex C5)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tvr {
+    OfflineDataAuthenticationNotPerformed,
+    StaticDataAuthenticationFailed,
+    IccDataMissing,
+    CardOnExceptionFile,
+    DynamicDataAuthenticationFailed,
+    CombinedDataAuthenticationFailed,
+    StaticDataAuthenticationSelected,
+    DifferentApplicationVersions,
+    ExpiredApplication,
+    ApplicationNotYetEffective,
+    ServiceNotAllowed,
+    NewCard,
+    CardholderVerificationFailed,
+    UnrecognisedCvm,
+    PinTryLimitExceeded,
+    PinPadNotPresent,
+    PinNotEntered,
+    OnlinePinEntered,
+    FloorLimitExceeded,
+    LowerConsecutiveOfflineLimitExceeded,
+    UpperConsecutiveOfflineLimitExceeded,
+    RandomlySelectedForOnline,
+    MerchantForcedOnline,
+    DefaultTdolUsed,
+    IssuerAuthenticationFailed,
+    ScriptFailedBeforeFinalGenerateAc,
+    ScriptFailedAfterFinalGenerateAc,
+}
+
+impl Tvr {
+    pub const ALL: [Tvr; 27] = [
+        Tvr::OfflineDataAuthenticationNotPerformed,
+        Tvr::StaticDataAuthenticationFailed,
+        Tvr::IccDataMissing,
+        Tvr::CardOnExceptionFile,
+        Tvr::DynamicDataAuthenticationFailed,
+        Tvr::CombinedDataAuthenticationFailed,
+        Tvr::StaticDataAuthenticationSelected,
+        Tvr::DifferentApplicationVersions,
+        Tvr::ExpiredApplication,
+        Tvr::ApplicationNotYetEffective,
+        Tvr::ServiceNotAllowed,
+        Tvr::NewCard,
+        Tvr::CardholderVerificationFailed,
+        Tvr::UnrecognisedCvm,
+        Tvr::PinTryLimitExceeded,
+        Tvr::PinPadNotPresent,
+        Tvr::PinNotEntered,
+        Tvr::OnlinePinEntered,
+        Tvr::FloorLimitExceeded,
+        Tvr::LowerConsecutiveOfflineLimitExceeded,
+        Tvr::UpperConsecutiveOfflineLimitExceeded,
+        Tvr::RandomlySelectedForOnline,
+        Tvr::MerchantForcedOnline,
+        Tvr::DefaultTdolUsed,
+        Tvr::IssuerAuthenticationFailed,
+        Tvr::ScriptFailedBeforeFinalGenerateAc,
+        Tvr::ScriptFailedAfterFinalGenerateAc,
+    ];
+
+    /// Index of the byte holding the bit and its mask
+    pub fn position(&self) -> (usize, u8) {
+        match self {
+            Tvr::OfflineDataAuthenticationNotPerformed => (0, 0x80),
+            Tvr::StaticDataAuthenticationFailed => (0, 0x40),
+            Tvr::IccDataMissing => (0, 0x20),
+            Tvr::CardOnExceptionFile => (0, 0x10),
+            Tvr::DynamicDataAuthenticationFailed => (0, 0x08),
+            Tvr::CombinedDataAuthenticationFailed => (0, 0x04),
+            Tvr::StaticDataAuthenticationSelected => (0, 0x02),
+            Tvr::DifferentApplicationVersions => (1, 0x80),
+            Tvr::ExpiredApplication => (1, 0x40),
+            Tvr::ApplicationNotYetEffective => (1, 0x20),
+            Tvr::ServiceNotAllowed => (1, 0x10),
+            Tvr::NewCard => (1, 0x08),
+            Tvr::CardholderVerificationFailed => (2, 0x80),
+            Tvr::UnrecognisedCvm => (2, 0x40),
+            Tvr::PinTryLimitExceeded => (2, 0x20),
+            Tvr::PinPadNotPresent => (2, 0x10),
+            Tvr::PinNotEntered => (2, 0x08),
+            Tvr::OnlinePinEntered => (2, 0x04),
+            Tvr::FloorLimitExceeded => (3, 0x80),
+            Tvr::LowerConsecutiveOfflineLimitExceeded => (3, 0x40),
+            Tvr::UpperConsecutiveOfflineLimitExceeded => (3, 0x20),
+            Tvr::RandomlySelectedForOnline => (3, 0x10),
+            Tvr::MerchantForcedOnline => (3, 0x08),
+            Tvr::DefaultTdolUsed => (4, 0x80),
+            Tvr::IssuerAuthenticationFailed => (4, 0x40),
+            Tvr::ScriptFailedBeforeFinalGenerateAc => (4, 0x20),
+            Tvr::ScriptFailedAfterFinalGenerateAc => (4, 0x10),
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Tvr::OfflineDataAuthenticationNotPerformed => "Offline data authentication was not performed",
+            Tvr::StaticDataAuthenticationFailed => "SDA failed",
+            Tvr::IccDataMissing => "ICC data missing",
+            Tvr::CardOnExceptionFile => "Card appears on terminal exception file",
+            Tvr::DynamicDataAuthenticationFailed => "DDA failed",
+            Tvr::CombinedDataAuthenticationFailed => "CDA failed",
+            Tvr::StaticDataAuthenticationSelected => "SDA selected",
+            Tvr::DifferentApplicationVersions => "ICC and terminal have different application versions",
+            Tvr::ExpiredApplication => "Expired application",
+            Tvr::ApplicationNotYetEffective => "Application not yet effective",
+            Tvr::ServiceNotAllowed => "Requested service not allowed for card product",
+            Tvr::NewCard => "New card",
+            Tvr::CardholderVerificationFailed => "Cardholder verification was not successful",
+            Tvr::UnrecognisedCvm => "Unrecognised CVM",
+            Tvr::PinTryLimitExceeded => "PIN Try Limit exceeded",
+            Tvr::PinPadNotPresent => "PIN entry required and PIN pad not present or not working",
+            Tvr::PinNotEntered => "PIN entry required, PIN pad present, but PIN was not entered",
+            Tvr::OnlinePinEntered => "Online PIN entered",
+            Tvr::FloorLimitExceeded => "Transaction exceeds floor limit",
+            Tvr::LowerConsecutiveOfflineLimitExceeded => "Lower consecutive offline limit exceeded",
+            Tvr::UpperConsecutiveOfflineLimitExceeded => "Upper consecutive offline limit exceeded",
+            Tvr::RandomlySelectedForOnline => "Transaction selected randomly for online processing",
+            Tvr::MerchantForcedOnline => "Merchant forced transaction online",
+            Tvr::DefaultTdolUsed => "Default TDOL used",
+            Tvr::IssuerAuthenticationFailed => "Issuer authentication failed",
+            Tvr::ScriptFailedBeforeFinalGenerateAc => "Script processing failed before final GENERATE AC",
+            Tvr::ScriptFailedAfterFinalGenerateAc => "Script processing failed after final GENERATE AC",
+        }
+    }
+
+    /// Bits set in the TVR value
+    pub fn decode(tvr: &[u8]) -> Vec<Tvr> {
+        Tvr::ALL.iter().copied().filter(|bit| is_set(tvr, bit.position())).collect()
+    }
+}
+
+impl fmt::Display for Tvr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+/// Bits of the Transaction Status Information (9B), recording which functions were performed (EMV Book 3 Annex C6)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tsi {
+    OfflineDataAuthenticationPerformed,
+    CardholderVerificationPerformed,
+    CardRiskManagementPerformed,
+    IssuerAuthenticationPerformed,
+    TerminalRiskManagementPerformed,
+    ScriptProcessingPerformed,
+}
+
+impl Tsi {
+    pub const ALL: [Tsi; 6] = [
+        Tsi::OfflineDataAuthenticationPerformed,
+        Tsi::CardholderVerificationPerformed,
+        Tsi::CardRiskManagementPerformed,
+        Tsi::IssuerAuthenticationPerformed,
+        Tsi::TerminalRiskManagementPerformed,
+        Tsi::ScriptProcessingPerformed,
+    ];
+
+    /// Index of the byte holding the bit and its mask
+    pub fn position(&self) -> (usize, u8) {
+        match self {
+            Tsi::OfflineDataAuthenticationPerformed => (0, 0x80),
+            Tsi::CardholderVerificationPerformed => (0, 0x40),
+            Tsi::CardRiskManagementPerformed => (0, 0x20),
+            Tsi::IssuerAuthenticationPerformed => (0, 0x10),
+            Tsi::TerminalRiskManagementPerformed => (0, 0x08),
+            Tsi::ScriptProcessingPerformed => (0, 0x04),
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Tsi::OfflineDataAuthenticationPerformed => "Offline data authentication was performed",
+            Tsi::CardholderVerificationPerformed => "Cardholder verification was performed",
+            Tsi::CardRiskManagementPerformed => "Card risk management was performed",
+            Tsi::IssuerAuthenticationPerformed => "Issuer authentication was performed",
+            Tsi::TerminalRiskManagementPerformed => "Terminal risk management was performed",
+            Tsi::ScriptProcessingPerformed => "Script processing was performed",
+        }
+    }
+
+    /// Bits set in the TSI value
+    pub fn decode(tsi: &[u8]) -> Vec<Tsi> {
+        Tsi::ALL.iter().copied().filter(|bit| is_set(tsi, bit.position())).collect()
+    }
+}
+
+impl fmt::Display for Tsi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+fn is_set(value: &[u8], (byte, mask): (usize, u8)) -> bool {
+    value.get(byte).is_some_and(|value| value & mask != 0)
+}
@@ -0,0 +1,19 @@
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+
+/// Raw RSA operation, which recovers the data signed with the private key when given the public exponent, and signs
+/// when given the private one. The result takes the length of the modulus.
+pub fn rsa(data: &[u8], modulus: &[u8], exponent: &[u8]) -> Vec<u8> {
+    let modulus_value = BigUint::from_bytes_be(modulus);
+    if modulus_value == BigUint::default() {
+        return vec![0x00; modulus.len()];
+    }
+    let result = BigUint::from_bytes_be(data).modpow(&BigUint::from_bytes_be(exponent), &modulus_value).to_bytes_be();
+    let mut padded = vec![0x00; modulus.len().saturating_sub(result.len())];
+    padded.extend(result);
+    padded
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
@@ -41,8 +41,9 @@ impl Hexadecimal for &str {
 
     fn to_u16(&self) -> u16 {
         let data = Vec::from_hex(self).expect("Unable to parse string to u16");
-        assert_eq!(data.len(), 2);
-        ((data[0] as u16) << 8) | (data[1] as u16)
+        // Single byte values, such as one byte tags, are allowed
+        assert!(matches!(data.len(), 1 | 2));
+        data.iter().fold(0, |value, byte| (value << 8) | (*byte as u16))
     }
 
     fn to_vec_u8(&self) -> Vec<u8> {
@@ -1 +1,3 @@
+pub mod crypto;
 pub mod extension;
+pub mod system;
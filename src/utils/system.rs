@@ -0,0 +1,50 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current UTC date as n6 YYMMDD
+pub fn date() -> [u8; 3] {
+    let days = seconds() / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    [bcd((year % 100) as u8), bcd(month), bcd(day)]
+}
+
+/// Current UTC time as n6 HHMMSS
+pub fn time() -> [u8; 3] {
+    let seconds = seconds() % 86400;
+    [bcd((seconds / 3600) as u8), bcd((seconds / 60 % 60) as u8), bcd((seconds % 60) as u8)]
+}
+
+/// Unpredictable bytes, seeded by the randomly keyed hasher of the standard library
+pub fn random(length: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(length);
+    while bytes.len() < length {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(bytes.len());
+        bytes.extend(hasher.finish().to_be_bytes());
+    }
+    bytes.truncate(length);
+    bytes
+}
+
+fn seconds() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+fn bcd(value: u8) -> u8 {
+    (value / 10) << 4 | (value % 10)
+}
+
+/// Gregorian date of a number of days since 1970-01-01, after Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}